@@ -0,0 +1,188 @@
+//! The `document` module defines the styled text model chapters are parsed into.
+//!
+//! A chapter is a plain text string split into blocks, each of which holds
+//! inline spans. Blocks and spans refer to the text by byte ranges, so the text
+//! itself stays free of any presentation and can be searched or exported as is.
+
+use core::ops::Range;
+
+/// Inline style flags of a span.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Style(u8);
+
+impl Style {
+    pub const BOLD: Style = Style(1);
+    pub const ITALIC: Style = Style(1 << 1);
+    pub const UNDERLINE: Style = Style(1 << 2);
+
+    pub fn contains(self, other: Style) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn union(self, other: Style) -> Style {
+        Style(self.0 | other.0)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BlockKind {
+    Paragraph,
+    /// A heading with its level, 1 to 6.
+    Heading(u8),
+    ListItem,
+    /// Text with its whitespace preserved.
+    Preformatted,
+    /// A horizontal rule, without any text.
+    Rule,
+    /// An image, without any text.
+    Image,
+}
+
+/// A run of text sharing the same style and link target.
+#[derive(Clone, Debug)]
+pub struct Span {
+    pub range: Range<usize>,
+    pub style: Style,
+    pub link: Option<String>,
+}
+
+/// A hyperlink and the text it covers.
+#[derive(Clone, Debug)]
+pub struct Link {
+    pub range: Range<usize>,
+    /// The `href` of the link, as written in the chapter.
+    pub target: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct Block {
+    pub kind: BlockKind,
+    pub range: Range<usize>,
+    pub spans: Vec<Span>,
+}
+
+/// Collects text into blocks and spans while walking a chapter.
+pub struct Builder {
+    text: String,
+    blocks: Vec<Block>,
+    kind: BlockKind,
+    start: usize,
+    spans: Vec<Span>,
+    /// Style of the text pushed next.
+    pub style: Style,
+    /// Link target of the text pushed next.
+    pub link: Option<String>,
+    // Whether a space separates the next word from the previous one.
+    space: bool,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Builder {
+            text: String::new(),
+            blocks: Vec::new(),
+            kind: BlockKind::Paragraph,
+            start: 0,
+            spans: Vec::new(),
+            style: Style::default(),
+            link: None,
+            space: false,
+        }
+    }
+
+    /// Offset into the text where the next text will be pushed.
+    pub fn offset(&self) -> usize {
+        self.text.len()
+    }
+
+    /// Ends the current block and starts a new one of `kind`.
+    pub fn start_block(&mut self, kind: BlockKind) {
+        // Keep the bullet of an item which wraps its text in a paragraph.
+        if self.text.len() == self.start && self.kind == BlockKind::ListItem {
+            return;
+        }
+        self.end_block();
+        self.kind = kind;
+    }
+
+    /// Ends the current block, following text goes into a new paragraph.
+    pub fn end_block(&mut self) {
+        let has_text = self.text.len() > self.start;
+        if has_text || matches!(self.kind, BlockKind::Rule | BlockKind::Image) {
+            self.blocks.push(Block {
+                kind: self.kind,
+                range: self.start..self.text.len(),
+                spans: std::mem::take(&mut self.spans),
+            });
+            self.text.push('\n');
+        }
+        self.kind = BlockKind::Paragraph;
+        self.start = self.text.len();
+        self.spans.clear();
+        self.space = false;
+    }
+
+    /// Pushes text, collapsing runs of whitespace into a single space.
+    pub fn push_text(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if text.starts_with(char::is_whitespace) {
+            self.space = true;
+        }
+        for word in text.split_ascii_whitespace() {
+            if self.space && !self.at_line_start() {
+                self.push_raw(" ");
+            }
+            self.push_raw(word);
+            self.space = true;
+        }
+        self.space = text.ends_with(char::is_whitespace);
+    }
+
+    /// Pushes text as is, keeping its whitespace.
+    pub fn push_raw(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+
+        let start = self.text.len();
+        self.text.push_str(text);
+
+        match self.spans.last_mut() {
+            Some(span)
+                if span.range.end == start
+                    && span.style == self.style
+                    && span.link == self.link =>
+            {
+                span.range.end = self.text.len();
+            }
+            _ => self.spans.push(Span {
+                range: start..self.text.len(),
+                style: self.style,
+                link: self.link.clone(),
+            }),
+        }
+    }
+
+    /// Breaks the line without ending the block.
+    pub fn line_break(&mut self) {
+        self.push_raw("\n");
+        self.space = false;
+    }
+
+    /// Range of the text pushed since `start`, without leading whitespace.
+    pub fn range_from(&self, start: usize) -> Range<usize> {
+        let pushed = &self.text[start..];
+        start + pushed.len() - pushed.trim_start().len()..self.text.len()
+    }
+
+    fn at_line_start(&self) -> bool {
+        self.text.len() == self.start || self.text.ends_with('\n')
+    }
+
+    pub fn finish(mut self) -> (String, Vec<Block>) {
+        self.end_block();
+        (self.text, self.blocks)
+    }
+}
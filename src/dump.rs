@@ -0,0 +1,117 @@
+//! The `dump` module writes book text to stdout without the interactive reader.
+
+use core::ops::Range;
+use std::io::{stdout, ErrorKind, Read, Seek, Write};
+
+use super::epub::Epub;
+use super::layout::Layout;
+use super::render::{plain_line, render_line};
+use super::Result;
+
+/// Part of the book to write.
+pub enum Selection {
+    All,
+    /// Indices of chapters in the spine.
+    Chapters(Range<usize>),
+    /// Index of a table of contents entry.
+    TocEntry(usize),
+}
+
+impl Selection {
+    /// Parses a chapter range given as `N`, `FROM-TO`, `FROM-` or `-TO`,
+    /// counting chapters from 1.
+    pub fn parse_chapters(range: &str) -> Option<Selection> {
+        let number = |n: &str, default: usize| match n.trim() {
+            "" => Some(default),
+            n => n.parse::<usize>().ok().filter(|&n| n > 0),
+        };
+
+        let (from, to) = match range.split_once('-') {
+            Some((from, to)) => (number(from, 1)?, number(to, usize::MAX)?),
+            None => {
+                let n = number(range, 0)?;
+                (n, n)
+            }
+        };
+        if from == 0 || from > to {
+            return None;
+        }
+        Some(Selection::Chapters(from - 1..to))
+    }
+}
+
+/// Writes the selected part of the book to stdout, wrapped to `width`.
+/// Styles are written as escape codes only if `color` is set.
+pub fn dump_ebook<R: Read + Seek>(
+    ebook: &mut Epub<R>,
+    selection: Selection,
+    width: u16,
+    color: bool,
+) -> Result<()> {
+    // Non-linear chapters are only written when asked for by number, or
+    // when the table of contents entry starts in one.
+    let linear_only = !matches!(selection, Selection::Chapters(_));
+    let from_toc = matches!(selection, Selection::TocEntry(_));
+
+    // Start and end of the text to write, as chapter indices and offsets.
+    let count = ebook.chapters.len();
+    let (start, end) = match selection {
+        Selection::All => ((0, 0), (count, 0)),
+        Selection::Chapters(range) => ((range.start, 0), (range.end.min(count), 0)),
+        Selection::TocEntry(entry) => {
+            // The entry ends where the next one that is not nested in it starts.
+            let entries = ebook.toc_entries();
+            let depth = entries[entry].depth;
+            let next = (entry + 1..entries.len()).find(|&i| entries[i].depth <= depth);
+            let start = ebook.toc_position(entry)?;
+            let end = match next {
+                Some(next) => ebook.toc_position(next)?,
+                None => (count, 0),
+            };
+            (start, end)
+        }
+    };
+
+    let mut out = stdout().lock();
+    let mut first = true;
+    for index in start.0..count.min(end.0 + 1) {
+        let toc_start = from_toc && index == start.0;
+        if linear_only && !toc_start && !ebook.chapters[index].linear {
+            continue;
+        }
+        let text_len = ebook.read_chapter(index)?.len();
+        let from = if index == start.0 { start.1 } else { 0 };
+        let to = if index == end.0 { end.1 } else { text_len };
+        if from >= to {
+            continue;
+        }
+
+        let chapter = &ebook.chapters[index];
+        let layout = Layout::new(chapter, width.max(1) as usize);
+        let mut page = String::new();
+        if !first {
+            page.push('\n');
+        }
+        first = false;
+        let lines = layout
+            .lines
+            .iter()
+            .skip_while(|line| line.range.end < from || line.block.is_none())
+            .take_while(|line| line.range.start < to);
+        for line in lines {
+            if color {
+                page += &render_line(chapter, line, &[]);
+            } else {
+                page += &plain_line(chapter, line);
+            }
+            page.push('\n');
+        }
+
+        // Stop quietly once the reading end of a pipe is closed.
+        match out.write_all(page.as_bytes()) {
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => return Ok(()),
+            result => result?,
+        }
+    }
+    Ok(())
+}
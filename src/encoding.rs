@@ -0,0 +1,185 @@
+//! The `encoding` module decodes the XML files of a book to UTF-8.
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+    Windows1252,
+}
+
+// Characters of the bytes 0x80 to 0x9F in Windows-1252, the other bytes are
+// the same as in Latin-1. Unassigned bytes map to the control characters.
+const WINDOWS_1252: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}', '\u{178}',
+];
+
+/// Decodes an XML document to UTF-8, going by its byte order mark or else the
+/// encoding in its XML declaration. Returns the name of the encoding if it
+/// isn't supported.
+pub fn decode_xml(data: Vec<u8>) -> Result<String, String> {
+    let (encoding, bom_len) = match data[..] {
+        [0xEF, 0xBB, 0xBF, ..] => (Encoding::Utf8, 3),
+        [0xFF, 0xFE, ..] => (Encoding::Utf16Le, 2),
+        [0xFE, 0xFF, ..] => (Encoding::Utf16Be, 2),
+        // Without a byte order mark, UTF-16 shows in the `<?` of the declaration.
+        [b'<', 0, b'?', 0, ..] => (Encoding::Utf16Le, 0),
+        [0, b'<', 0, b'?', ..] => (Encoding::Utf16Be, 0),
+        _ => (declared_encoding(&data)?, 0),
+    };
+    let bytes = &data[bom_len..];
+
+    Ok(match encoding {
+        Encoding::Utf8 => match String::from_utf8(data) {
+            Ok(mut text) => {
+                text.drain(..bom_len);
+                text
+            }
+            // Older books leave out the declaration of their Windows encoding.
+            Err(err) => decode_windows_1252(&err.into_bytes()[bom_len..]),
+        },
+        Encoding::Utf16Le => decode_utf16(bytes, u16::from_le_bytes),
+        Encoding::Utf16Be => decode_utf16(bytes, u16::from_be_bytes),
+        Encoding::Latin1 => bytes.iter().map(|&b| b as char).collect(),
+        Encoding::Windows1252 => decode_windows_1252(bytes),
+    })
+}
+
+// Reads the encoding from the XML declaration, UTF-8 if there is none.
+fn declared_encoding(data: &[u8]) -> Result<Encoding, String> {
+    let Some(declaration) = data.strip_prefix(b"<?xml") else {
+        return Ok(Encoding::Utf8);
+    };
+    let end = declaration.windows(2).position(|w| w == b"?>").unwrap_or(0);
+    let declaration = String::from_utf8_lossy(&declaration[..end]);
+    let Some((_, rest)) = declaration.split_once("encoding") else {
+        return Ok(Encoding::Utf8);
+    };
+    let rest = rest.trim_start().trim_start_matches('=').trim_start();
+    let label: String = rest
+        .chars()
+        .skip(1)
+        .take_while(|&c| c != '"' && c != '\'')
+        .collect();
+
+    match label.to_ascii_lowercase().as_str() {
+        // A declaration readable as ASCII isn't in UTF-16, whatever it says.
+        "utf-8" | "utf8" | "utf-16" | "utf-16le" | "utf-16be" => Ok(Encoding::Utf8),
+        "iso-8859-1" | "iso8859-1" | "iso_8859-1" | "latin1" | "latin-1" | "l1" | "us-ascii"
+        | "ascii" => Ok(Encoding::Latin1),
+        "windows-1252" | "cp1252" | "x-cp1252" => Ok(Encoding::Windows1252),
+        _ => Err(label),
+    }
+}
+
+fn decode_utf16(bytes: &[u8], to_unit: fn([u8; 2]) -> u16) -> String {
+    let units = bytes
+        .chunks_exact(2)
+        .map(|pair| to_unit([pair[0], pair[1]]));
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+fn decode_windows_1252(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| match b {
+            0x80..=0x9F => WINDOWS_1252[(b - 0x80) as usize],
+            _ => b as char,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Encodes the text as UTF-16, with the bytes of each unit in `order`.
+    fn utf16(text: &str, order: fn(u16) -> [u8; 2]) -> Vec<u8> {
+        text.encode_utf16().flat_map(order).collect()
+    }
+
+    #[test]
+    fn byte_order_marks() {
+        let mut data = b"\xEF\xBB\xBF".to_vec();
+        data.extend("<?xml version='1.0' encoding='windows-1252'?><a>é</a>".as_bytes());
+        assert_eq!(
+            decode_xml(data).unwrap(),
+            "<?xml version='1.0' encoding='windows-1252'?><a>é</a>"
+        );
+
+        let text = "<?xml version='1.0'?><a>\u{1D11E} é</a>";
+        let mut data = vec![0xFF, 0xFE];
+        data.extend(utf16(text, u16::to_le_bytes));
+        assert_eq!(decode_xml(data).unwrap(), text);
+        let mut data = vec![0xFE, 0xFF];
+        data.extend(utf16(text, u16::to_be_bytes));
+        assert_eq!(decode_xml(data).unwrap(), text);
+    }
+
+    #[test]
+    fn utf16_without_byte_order_mark() {
+        let text = "<?xml version='1.0' encoding='UTF-16'?><a>é</a>";
+        assert_eq!(decode_xml(utf16(text, u16::to_le_bytes)).unwrap(), text);
+        assert_eq!(decode_xml(utf16(text, u16::to_be_bytes)).unwrap(), text);
+    }
+
+    #[test]
+    fn unpaired_surrogates() {
+        let data = [0x3C, 0x00, 0x00, 0xD8, 0x3E, 0x00];
+        assert_eq!(decode_utf16(&data, u16::from_le_bytes), "<\u{FFFD}>");
+    }
+
+    #[test]
+    fn declared_encodings() {
+        let declared = |xml: &str| declared_encoding(xml.as_bytes());
+        assert_eq!(declared("<a/>"), Ok(Encoding::Utf8));
+        assert_eq!(declared("<?xml version='1.0'?><a/>"), Ok(Encoding::Utf8));
+        assert_eq!(
+            declared(r#"<?xml version="1.0" encoding="UTF-8"?>"#),
+            Ok(Encoding::Utf8)
+        );
+        assert_eq!(
+            declared("<?xml encoding = 'ISO-8859-1'?>"),
+            Ok(Encoding::Latin1)
+        );
+        assert_eq!(
+            declared("<?xml encoding='cp1252'?>"),
+            Ok(Encoding::Windows1252)
+        );
+        assert_eq!(declared("<?xml encoding='UTF-16'?>"), Ok(Encoding::Utf8));
+        assert_eq!(
+            declared("<?xml encoding='Shift_JIS'?>"),
+            Err("Shift_JIS".to_string())
+        );
+        // The encoding of the root element isn't the one of the document.
+        assert_eq!(
+            declared("<?xml?><a encoding='cp1252'/>"),
+            Ok(Encoding::Utf8)
+        );
+    }
+
+    #[test]
+    fn single_byte_encodings() {
+        let data = b"<?xml version='1.0' encoding='latin1'?><a>\xE9\x80</a>".to_vec();
+        assert!(decode_xml(data).unwrap().ends_with("<a>é\u{80}</a>"));
+        let data = b"<?xml version='1.0' encoding='windows-1252'?><a>\xE9\x80\x81</a>".to_vec();
+        assert!(decode_xml(data).unwrap().ends_with("<a>é€\u{81}</a>"));
+    }
+
+    #[test]
+    fn invalid_utf8_falls_back_to_windows_1252() {
+        let data = b"<?xml version='1.0'?><a>\x93quoted\x94 caf\xE9</a>".to_vec();
+        assert_eq!(
+            decode_xml(data).unwrap(),
+            "<?xml version='1.0'?><a>\u{201C}quoted\u{201D} café</a>"
+        );
+        let data = b"\xEF\xBB\xBF<a>\x80</a>".to_vec();
+        assert_eq!(decode_xml(data).unwrap(), "<a>€</a>");
+    }
+}
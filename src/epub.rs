@@ -1,180 +1,473 @@
-use super::xml::{Document, Node};
+use super::document::{Block, BlockKind, Builder, Link, Style};
+use super::encoding;
+use super::href;
+use super::toc::{self, Landmark, PageTarget, TocEntry};
+use super::xml::{self, Document, Node, ParsingOptions};
 use std::{
     collections::HashMap,
     fs::File,
-    io::Read,
-    path::{self, PathBuf},
+    io::{self, Cursor, Read, Seek},
+    path::PathBuf,
 };
 use zip::ZipArchive;
 
 use super::Result;
-use crate::error::{to_fnf_error, to_parse_error};
+use crate::error::{parse_error, to_fnf_error, to_parse_error};
 
 pub const EPUB_MIME_TYPE: &str = "application/epub+zip";
 
-pub struct Epub {
-    container: ZipArchive<File>,
-    root_dir: String,
-    pub file_path: path::PathBuf,
+/// The <http://www.idpf.org/2007/opf> URI of the `opf:` attributes.
+const NS_OPF: &str = "http://www.idpf.org/2007/opf";
+
+/// The <http://www.idpf.org/2007/ops> URI of the `epub:` attributes.
+pub const NS_OPS: &str = "http://www.idpf.org/2007/ops";
+const NS_XLINK: &str = "http://www.w3.org/1999/xlink";
+
+/// A book read from `R`, a file unless it was opened from memory.
+pub struct Epub<R = File> {
+    container: ZipArchive<R>,
+    /// Location of the book on disk, if it was opened from a file.
+    pub file_path: Option<PathBuf>,
+    /// The EPUB version from the package document, like `3.0`.
+    pub version: String,
     pub metadata: Option<Metadata>,
     pub chapters: Vec<Chapter>,
-    pub toc: Vec<(usize, String, String)>,
+    pub toc: Vec<TocEntry>,
+    pub landmarks: Vec<Landmark>,
+    /// Pages of the print edition, in reading order.
+    pub page_list: Vec<PageTarget>,
+    /// Whether pages progress from right to left, as in Japanese books.
+    pub right_to_left: bool,
+    /// Every resource of the book, in the order of the package manifest.
+    pub manifest: Vec<ManifestItem>,
+}
+
+/// The cover image of a book.
+#[derive(Debug)]
+pub struct Cover {
+    pub data: Vec<u8>,
+    pub media_type: String,
+}
+
+/// A resource listed in the package manifest.
+#[derive(Debug)]
+pub struct ManifestItem {
+    pub id: String,
+    /// Name of the resource's file in the container.
+    pub path: String,
+    pub media_type: String,
+    /// Like `cover-image`, `nav`, `scripted`, `svg` or `mathml`.
+    pub properties: Vec<String>,
+    /// Id of the item to show instead of this one if it can't be.
+    pub fallback: Option<String>,
+}
+
+/// A person or organisation responsible for the book.
+#[derive(Debug)]
+pub struct Creator {
+    pub name: String,
+    /// MARC relator code of the role, like `aut` or `edt`.
+    pub role: Option<String>,
+    /// Name used for sorting, like `Doe, Jane`.
+    pub file_as: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Title {
+    pub text: String,
+    /// Kind of title, like `main` or `subtitle`.
+    pub title_type: Option<String>,
 }
 
 #[derive(Debug)]
+pub struct Identifier {
+    pub value: String,
+    /// Kind of identifier, like `ISBN` or `UUID`.
+    pub scheme: Option<String>,
+}
+
+#[derive(Debug, Default)]
 pub struct Metadata {
-    title: Option<String>,
-    creator: Option<String>,
-    language: Option<String>,
-    date: Option<String>,
-    identifier: Option<String>,
-    description: Option<String>,
-    publisher: Option<String>,
+    pub titles: Vec<Title>,
+    pub creators: Vec<Creator>,
+    pub contributors: Vec<Creator>,
+    pub languages: Vec<String>,
+    pub identifiers: Vec<Identifier>,
+    pub subjects: Vec<String>,
+    pub date: Option<String>,
+    /// Last modification date, from `dcterms:modified`.
+    pub modified: Option<String>,
+    pub description: Option<String>,
+    pub publisher: Option<String>,
+    pub rights: Option<String>,
+    pub series: Option<String>,
+    /// Position of the book in its series, which may be fractional.
+    pub series_index: Option<String>,
+    /// Manifest id of the cover image, from the EPUB2 `<meta name="cover">`.
+    pub cover: Option<String>,
+    // Index into `identifiers` of the package's unique identifier.
+    unique_identifier: Option<usize>,
 }
 
 #[derive(Debug)]
 pub struct Chapter {
-    pub relative_path: String,
+    /// Name of the chapter's file in the container.
+    pub path: String,
+    /// Plain text of the chapter, every block ends with a newline.
     pub text: String,
+    pub blocks: Vec<Block>,
+    pub links: Vec<Link>,
+    /// Footnotes taken out of the text, each parsed like a chapter of its own.
+    pub notes: Vec<Chapter>,
+    /// Whether the chapter is part of the reading order, `false` for spine
+    /// items with `linear="no"` which are only reached through links.
+    pub linear: bool,
+    /// The `properties` of the spine item, like `page-spread-left`.
+    pub properties: Vec<String>,
+    /// Errors in the markup which parsing recovered from.
+    pub warnings: Vec<xml::Error>,
     ids: Vec<(String, usize)>,
     is_parsed: bool,
-    // pub lines: Vec<(usize, usize)>,
 }
 
 impl Metadata {
-    fn new(metadata_node: Node) -> Self {
-        let mut metadata = Metadata {
-            title: None,
-            creator: None,
-            language: None,
-            identifier: None,
-            publisher: None,
-            date: None,
-            description: None,
+    /// Reads the metadata element, `unique_id` is the id of the package's
+    /// unique identifier.
+    fn new(metadata_node: Node, unique_id: Option<&str>) -> Self {
+        let mut metadata = Metadata::default();
+
+        // EPUB3 refines elements by id with `<meta refines="#id" property="...">`.
+        let mut refines: HashMap<&str, Vec<(&str, String)>> = HashMap::new();
+        for meta in metadata_node.children().filter(|n| n.has_tag_name("meta")) {
+            if let (Some(id), Some(property), Some(value)) = (
+                meta.attribute("refines"),
+                meta.attribute("property"),
+                meta.text(),
+            ) {
+                let id = id.strip_prefix('#').unwrap_or(id);
+                let mut value = value.trim().to_string();
+                if meta.attribute("scheme") == Some("onix:codelist5") {
+                    value = onix_identifier_type(&value).map_or(value, String::from);
+                }
+                refines.entry(id).or_default().push((property, value));
+            }
+        }
+        let refined = |n: Node, property: &str| {
+            let properties = refines.get(n.attribute("id")?)?;
+            properties
+                .iter()
+                .find(|(p, _)| *p == property)
+                .map(|(_, v)| v.clone())
         };
 
-        for child in metadata_node.children() {
-            if child.is_element() {
-                match child.tag_name().name() {
-                    "title" => metadata.title = child.text().map(String::from),
-                    "creator" => metadata.creator = child.text().map(String::from),
-                    "language" => metadata.language = child.text().map(String::from),
-                    "identifier" => metadata.identifier = child.text().map(String::from),
-                    "publisher" => metadata.publisher = child.text().map(String::from),
-                    "description" => metadata.description = child.text().map(String::from),
-                    "date" => metadata.date = child.text().map(String::from),
-                    _ => {}
+        for child in metadata_node.children().filter(Node::is_element) {
+            let name = child.tag_name().name();
+            if name == "meta" {
+                metadata.parse_meta(child, &refines);
+                continue;
+            }
+
+            let Some(text) = child.text().map(str::trim).filter(|t| !t.is_empty()) else {
+                continue;
+            };
+            let text = text.to_string();
+            match name {
+                "title" => metadata.titles.push(Title {
+                    text,
+                    title_type: refined(child, "title-type"),
+                }),
+                "creator" | "contributor" => {
+                    let creator = Creator {
+                        name: text,
+                        role: child
+                            .attribute((NS_OPF, "role"))
+                            .map(String::from)
+                            .or_else(|| refined(child, "role")),
+                        file_as: child
+                            .attribute((NS_OPF, "file-as"))
+                            .map(String::from)
+                            .or_else(|| refined(child, "file-as")),
+                    };
+                    match name {
+                        "creator" => metadata.creators.push(creator),
+                        _ => metadata.contributors.push(creator),
+                    }
                 }
+                "identifier" => {
+                    if unique_id.is_some() && child.attribute("id") == unique_id {
+                        metadata.unique_identifier = Some(metadata.identifiers.len());
+                    }
+                    let scheme = child
+                        .attribute((NS_OPF, "scheme"))
+                        .map(String::from)
+                        .or_else(|| refined(child, "identifier-type"))
+                        .or_else(|| identifier_scheme(&text));
+                    metadata.identifiers.push(Identifier {
+                        value: text,
+                        scheme,
+                    });
+                }
+                "language" => metadata.languages.push(text),
+                "subject" => metadata.subjects.push(text),
+                "publisher" => metadata.publisher = Some(text),
+                "description" => metadata.description = Some(text),
+                "date" => metadata.date = Some(text),
+                "rights" => metadata.rights = Some(text),
+                _ => {}
             }
         }
 
-        return metadata;
+        metadata
+    }
+
+    // Reads an EPUB3 `<meta property>` or an EPUB2 `<meta name content>`.
+    fn parse_meta(&mut self, meta: Node, refines: &HashMap<&str, Vec<(&str, String)>>) {
+        if meta.attribute("refines").is_some() {
+            return;
+        }
+
+        let (name, value) = match (meta.attribute("property"), meta.attribute("name")) {
+            (Some(property), _) => (property, meta.text().map(str::trim)),
+            (None, Some(name)) => (name, meta.attribute("content").map(str::trim)),
+            (None, None) => return,
+        };
+        let Some(value) = value.filter(|v| !v.is_empty()).map(String::from) else {
+            return;
+        };
+
+        match name {
+            "dcterms:modified" => self.modified = Some(value),
+            "calibre:series" => self.series = Some(value),
+            "calibre:series_index" => self.series_index = Some(value),
+            "cover" => self.cover = Some(value),
+            "belongs-to-collection" => {
+                let position = meta
+                    .attribute("id")
+                    .and_then(|id| refines.get(id))
+                    .and_then(|properties| properties.iter().find(|(p, _)| *p == "group-position"));
+                self.series = Some(value);
+                self.series_index = position.map(|(_, v)| v.clone());
+            }
+            _ => {}
+        }
+    }
+
+    /// The unique identifier of the book, or its first identifier.
+    pub fn identifier(&self) -> Option<&str> {
+        let index = self.unique_identifier.unwrap_or(0);
+        self.identifiers.get(index).map(|i| i.value.as_str())
+    }
+
+    /// The main title of the book, or its first title.
+    pub fn title(&self) -> Option<&str> {
+        self.titles
+            .iter()
+            .find(|t| t.title_type.as_deref() == Some("main"))
+            .or(self.titles.first())
+            .map(|t| t.text.as_str())
+    }
+
+    pub fn creator(&self) -> Option<&str> {
+        self.creators.first().map(|c| c.name.as_str())
+    }
+}
+
+impl ManifestItem {
+    pub fn has_property(&self, property: &str) -> bool {
+        self.properties.iter().any(|p| p == property)
+    }
+
+    /// Whether the item is a document which can be read as a chapter.
+    pub fn is_xhtml(&self) -> bool {
+        // Some books declare their XHTML documents as HTML.
+        matches!(
+            self.media_type.as_str(),
+            "application/xhtml+xml" | "text/html"
+        )
     }
 }
 
 impl Chapter {
     fn new(path: &str) -> Self {
         Chapter {
-            relative_path: path.to_string(),
+            path: path.to_string(),
             text: String::new(),
+            blocks: Vec::new(),
+            links: Vec::new(),
+            notes: Vec::new(),
+            linear: true,
+            properties: Vec::new(),
+            warnings: Vec::new(),
             ids: Vec::new(),
             is_parsed: false,
         }
     }
 
-    fn parse_children(&mut self, node: Node) {
+    /// Returns the offset into `text` of the element with the given id.
+    pub fn id_offset(&self, id: &str) -> Option<usize> {
+        self.ids
+            .iter()
+            .find(|(i, _)| i == id)
+            .map(|(_, offset)| *offset)
+    }
+
+    /// Returns the index into `notes` of the footnote containing the id.
+    pub fn note_index(&self, id: &str) -> Option<usize> {
+        self.notes
+            .iter()
+            .position(|note| note.id_offset(id).is_some())
+    }
+
+    fn parse(&mut self, body: Node) {
+        let mut builder = Builder::new();
+        self.parse_node(&mut builder, body);
+        (self.text, self.blocks) = builder.finish();
+    }
+
+    fn parse_children(&mut self, b: &mut Builder, node: Node) {
         for child in node.children() {
-            self.parse(child);
+            self.parse_node(b, child);
         }
     }
 
-    fn parse(&mut self, n: Node) {
+    // Parses the children of `n` with `style` added to the current style.
+    fn parse_styled(&mut self, b: &mut Builder, n: Node, style: Style) {
+        let outer = b.style;
+        b.style = outer.union(style);
+        self.parse_children(b, n);
+        b.style = outer;
+    }
+
+    fn parse_node(&mut self, b: &mut Builder, n: Node) {
         if n.is_text() {
-            let text = n.text().unwrap();
-            let content: Vec<_> = text.split_ascii_whitespace().collect();
+            b.push_text(n.text().unwrap());
+            return;
+        }
 
-            if text.starts_with(char::is_whitespace) {
-                self.text.push(' ');
-            }
-            self.text.push_str(&content.join(" "));
-            if text.ends_with(char::is_whitespace) {
-                self.text.push(' ');
+        let name = n.tag_name().name();
+        if name == "aside" && has_epub_type(n, &["footnote", "endnote", "rearnote", "note"]) {
+            let mut note = Chapter::new(&self.path);
+            if let Some(id) = n.attribute("id") {
+                note.ids.push((id.to_string(), 0));
             }
+            let mut builder = Builder::new();
+            note.parse_children(&mut builder, n);
+            (note.text, note.blocks) = builder.finish();
+            note.is_parsed = true;
+            self.notes.push(note);
             return;
         }
 
+        let block = match name {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                Some(BlockKind::Heading(name.as_bytes()[1] - b'0'))
+            }
+            "blockquote" | "div" | "p" | "tr" => Some(BlockKind::Paragraph),
+            "li" => Some(BlockKind::ListItem),
+            "pre" => Some(BlockKind::Preformatted),
+            "hr" => Some(BlockKind::Rule),
+            "img" | "image" => Some(BlockKind::Image),
+            _ => None,
+        };
+
+        // Start the block first, so that ids point into it.
+        if let Some(kind) = block {
+            b.start_block(kind);
+        }
+
         if let Some(id) = n.attribute("id") {
-            self.ids.push((id.to_string(), self.text.len()));
+            self.ids.push((id.to_string(), b.offset()));
         }
 
-        match n.tag_name().name() {
-            "br" => self.text.push('\n'),
-            "hr" => self.text.push_str("\n* * *\n"),
-            "img" | "image" => self.text.push_str("\n[IMAGE]\n"),
+        match name {
+            "br" => b.line_break(),
+            "hr" | "img" | "image" => {}
             "a" => {
-                match n.attribute("href") {
+                let outer = b.link.take();
+                let href = n.attribute("href");
+                b.link = href.map(String::from);
+                let start = b.offset();
+                let noteref = has_epub_type(n, &["noteref"]);
+                if noteref {
+                    b.push_text("[");
+                }
+                match href {
                     // TODO open external urls in browser
                     Some(url) if !url.starts_with("http") => {
-                        // let start = c.text.len();
-                        self.text.push_str(&termion::style::Underline.to_string());
-                        self.parse_children(n);
-                        self.text.push_str(&termion::style::NoUnderline.to_string());
-                        // c.links.push((start, c.text.len(), url.to_string()));
+                        self.parse_styled(b, n, Style::UNDERLINE)
                     }
-                    _ => self.parse_children(n),
+                    _ => self.parse_children(b, n),
                 }
+                if noteref {
+                    b.push_text("]");
+                }
+                let range = b.range_from(start);
+                if let Some(href) = href.filter(|_| !range.is_empty()) {
+                    self.links.push(Link {
+                        range,
+                        target: href.to_string(),
+                    });
+                }
+                b.link = outer;
             }
-            "em" => {
-                self.text.push_str(&termion::style::Italic.to_string());
-                self.parse_children(n);
-                self.text.push_str(&termion::style::NoItalic.to_string());
-            }
-            "strong" => {
-                self.text.push_str(&termion::style::Bold.to_string());
-                self.parse_children(n);
-                self.text.push_str(&termion::style::Reset.to_string());
-            }
-            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
-                self.text.push('\n');
-                self.text.push_str(&termion::style::Bold.to_string());
-                self.parse_children(n);
-                self.text.push_str(&termion::style::Reset.to_string());
-                self.text.push('\n');
-            }
-            "blockquote" | "div" | "p" | "tr" => {
-                // TODO compress newlines
-                self.text.push('\n');
-                self.parse_children(n);
-                self.text.push('\n');
-            }
-            "li" => {
-                self.text.push_str("\n- ");
-                self.parse_children(n);
-                self.text.push('\n');
-            }
-            "pre" => {
-                self.text.push_str("\n  ");
-                n.descendants()
-                    .filter(Node::is_text)
-                    .map(|n| n.text().unwrap().replace('\n', "\n  "))
-                    .for_each(|s| self.text.push_str(&s));
-                self.text.push('\n');
-            }
-            _ => self.parse_children(n),
+            "em" | "i" => self.parse_styled(b, n, Style::ITALIC),
+            "strong" | "b" => self.parse_styled(b, n, Style::BOLD),
+            "pre" => n
+                .descendants()
+                .filter(Node::is_text)
+                .for_each(|n| b.push_raw(n.text().unwrap())),
+            _ => self.parse_children(b, n),
+        }
+
+        if block.is_some() {
+            b.end_block();
+        }
+
+        // Void elements only have children when a malformed chapter leaves them
+        // open, the children follow them.
+        if matches!(name, "br" | "hr" | "img") {
+            self.parse_children(b, n);
         }
     }
 }
 
-impl Epub {
+impl Epub<File> {
+    /// Opens the book at `path`.
     pub fn new(path: PathBuf) -> Result<Self> {
         let file = File::open(&path).map_err(|_| to_fnf_error(path.display().to_string()))?;
+        let mut epub = Epub::from_reader(file)?;
+        epub.file_path = Some(path);
+        Ok(epub)
+    }
+}
+
+impl Epub<Cursor<Vec<u8>>> {
+    /// Opens a book held in memory.
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self> {
+        Epub::from_reader(Cursor::new(data))
+    }
+
+    /// Reads a book from stdin into memory and opens it.
+    pub fn from_stdin() -> Result<Self> {
+        let mut data = Vec::new();
+        io::stdin().lock().read_to_end(&mut data)?;
+        Epub::from_bytes(data)
+    }
+}
 
+impl<R: Read + Seek> Epub<R> {
+    /// Opens the book read from `reader`.
+    pub fn from_reader(reader: R) -> Result<Self> {
         let mut epub = Epub {
-            file_path: path,
-            container: ZipArchive::new(file).map_err(|_| to_parse_error())?,
-            root_dir: String::new(),
+            file_path: None,
+            container: ZipArchive::new(reader).map_err(|_| to_parse_error())?,
+            version: String::new(),
             chapters: Vec::new(),
             toc: Vec::new(),
+            landmarks: Vec::new(),
+            page_list: Vec::new(),
+            right_to_left: false,
+            manifest: Vec::new(),
             metadata: None,
         };
         // check mimetype
@@ -187,125 +480,326 @@ impl Epub {
     }
 
     fn get_raw_text(&mut self, name: &str) -> Result<String> {
-        let mut text = String::new();
+        let data = self.get_raw_bytes(name)?;
+        encoding::decode_xml(data).map_err(|label| {
+            parse_error(&format!("{} has the unsupported encoding {}", name, label))
+        })
+    }
+
+    fn get_raw_bytes(&mut self, name: &str) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
         self.container
             .by_name(name)?
-            .read_to_string(&mut text)
+            .read_to_end(&mut data)
             .map_err(|_| to_parse_error())?;
-        Ok(text)
+        Ok(data)
     }
 
     fn parse_content_opf(&mut self) -> Result<()> {
         let xml = self.get_raw_text("META-INF/container.xml")?;
-        let doc = Document::parse(&xml)?;
+        let doc = parse_package(&xml)?;
         let path = doc
             .descendants()
             .find(|n| n.has_tag_name("rootfile"))
-            .ok_or_else(|| to_parse_error())?
-            .req_attribute("full-path")?;
+            .ok_or_else(|| parse_error("container.xml has no <rootfile> element"))?
+            .attribute("full-path")
+            .ok_or_else(|| parse_error("<rootfile> has no full-path attribute"))?;
 
         let xml = self.get_raw_text(path)?;
-        let content_opf = Document::parse(&xml)?;
+        let content_opf = parse_package(&xml)?;
 
-        self.root_dir = match path.rfind('/') {
-            Some(n) => &path[..=n],
-            None => "",
-        }
-        .to_string();
+        let package = content_opf.root_element();
+        let section = |name: &'static str| {
+            opf_children(package, name)
+                .next()
+                .ok_or_else(|| parse_error(&format!("Package document has no <{}> element", name)))
+        };
+        let metadata_node = section("metadata")?;
+        let manifest_node = section("manifest")?;
+        let spine_node = section("spine")?;
+        let version = package
+            .attribute("version")
+            .ok_or_else(|| parse_error("<package> has no version attribute"))?;
+        self.version = version.to_string();
+        let is_epub3 = version.split('.').next() == Some("3");
 
-        let mut children = content_opf
-            .root_element()
-            .children()
-            .filter(Node::is_element);
+        // Parse Ebook Metadata
+        let unique_id = package.attribute("unique-identifier");
+        self.metadata = Some(Metadata::new(metadata_node, unique_id));
 
-        let metadata_node = children.next().unwrap();
-        let manifest_node = children.next().unwrap();
-        let spine_node = children.next().unwrap();
-        let version = content_opf.root_element().req_attribute("version")?;
+        // Parse the resources of the ebook
+        for n in opf_children(manifest_node, "item") {
+            let id = n
+                .attribute("id")
+                .ok_or_else(|| parse_error("Manifest <item> has no id attribute"))?;
+            let href = n.attribute("href").ok_or_else(|| {
+                parse_error(&format!("Manifest <item> {} has no href attribute", id))
+            })?;
+            let Some(href) = href::resolve(path, href) else {
+                // Remote resources aren't in the container and are never read.
+                if href::has_scheme(href) {
+                    continue;
+                }
+                return Err(parse_error(&format!(
+                    "Manifest <item> {} has an href {} outside the book",
+                    id, href
+                )));
+            };
+            let properties = n.attribute("properties").unwrap_or_default();
+            self.manifest.push(ManifestItem {
+                id: id.to_string(),
+                path: href,
+                media_type: n.attribute("media-type").unwrap_or_default().to_string(),
+                properties: properties
+                    .split_ascii_whitespace()
+                    .map(String::from)
+                    .collect(),
+                fallback: n.attribute("fallback").map(String::from),
+            });
+        }
+        let nav_path = self
+            .manifest
+            .iter()
+            .find(|item| is_epub3 && item.has_property("nav"))
+            .map(|item| item.path.clone());
+        let ncx_path = self
+            .manifest
+            .iter()
+            .find(|item| item.media_type == "application/x-dtbncx+xml")
+            .map(|item| item.path.clone());
 
-        // Parse Ebook Metadata
-        self.metadata = Some(Metadata::new(metadata_node));
-
-        // Parse ebook chapter links in order
-        let mut manifest: HashMap<&str, &str> = HashMap::new();
-        let mut toc_file_path: Option<&str> = None;
-        for n in manifest_node.children().filter(Node::is_element) {
-            manifest.insert(n.req_attribute("id")?, n.req_attribute("href")?);
-            if version == "3.0" && n.attribute("properties") == Some("nav") {
-                toc_file_path = n.attribute("href");
-            } else if n.attribute("media-type") == Some("application/x-dtbncx+xml") {
-                toc_file_path = n.attribute("href");
+        // Parse Ebook Chapters
+        self.right_to_left = spine_node.attribute("page-progression-direction") == Some("rtl");
+        for node in opf_children(spine_node, "itemref") {
+            let id = node
+                .attribute("idref")
+                .ok_or_else(|| parse_error("Spine <itemref> has no idref attribute"))?;
+            let Some(item) = self.manifest_item(id) else {
+                return Err(parse_error(&format!(
+                    "Spine item {} is not in the manifest",
+                    id
+                )));
+            };
+            // Read a fallback of items which aren't XHTML, or the item itself if
+            // there is none.
+            let item = self.readable_item(item).unwrap_or(item);
+            let mut chapter = Chapter::new(&item.path);
+            chapter.linear = node.attribute("linear") != Some("no");
+            if let Some(properties) = node.attribute("properties") {
+                chapter.properties = properties
+                    .split_ascii_whitespace()
+                    .map(String::from)
+                    .collect();
             }
+            self.chapters.push(chapter);
         }
 
-        // Parse TOC
-        let mut nav: HashMap<String, (String, String)> = HashMap::new();
-        if let Some(toc_path) = toc_file_path {
-            let full_toc_path = format!("{}{}", self.root_dir, toc_path);
-            self.parse_toc(version, &full_toc_path, &mut nav)?;
+        // Parse TOC, preferring the navigation document over the NCX
+        if let Some(nav_path) = nav_path {
+            self.parse_toc(&nav_path, true)?;
+        }
+        if let (true, Some(ncx_path)) = (self.toc.is_empty(), ncx_path) {
+            self.parse_toc(&ncx_path, false)?;
+        }
+        let guide = opf_children(package, "guide").next();
+        if let (true, Some(guide)) = (self.landmarks.is_empty(), guide) {
+            let chapters = &self.chapters;
+            let chapter = |p: &str| chapters.iter().position(|c| c.path == p);
+            self.landmarks = toc::parse_guide(guide, path, &chapter);
         }
 
-        // Parse Ebook Chapters
-        for (i, node) in spine_node.children().filter(Node::is_element).enumerate() {
-            let id = node.req_attribute("idref")?;
-            if let Some(path) = manifest.remove(id) {
-                if let Some((exact_path, title)) = nav.remove(path) {
-                    self.toc.push((i, title, exact_path));
-                }
-                self.chapters.push(Chapter::new(path));
-            } else {
-                return Err(to_parse_error().into());
+        Ok(())
+    }
+
+    fn parse_toc(&mut self, path: &str, is_nav: bool) -> Result<()> {
+        let xml = self.get_raw_text(path)?;
+        let doc = if is_nav {
+            parse_xhtml(&xml)?
+        } else {
+            parse_package(&xml)?
+        };
+
+        let chapters = &self.chapters;
+        let chapter = |p: &str| chapters.iter().position(|c| c.path == p);
+        if is_nav {
+            self.toc = toc::parse_nav(&doc, path, &chapter);
+            self.landmarks = toc::parse_landmarks(&doc, path, &chapter);
+            self.page_list = toc::parse_page_list(&doc, path, &chapter);
+        } else {
+            self.toc = toc::parse_ncx(&doc, path, &chapter);
+            // The navigation document may have a page list without a toc.
+            if self.page_list.is_empty() {
+                self.page_list = toc::parse_ncx_page_list(&doc, path, &chapter);
             }
         }
+        Ok(())
+    }
 
-        return Ok(());
+    /// Returns the entries of the table of contents in reading order.
+    pub fn toc_entries(&self) -> Vec<&TocEntry> {
+        TocEntry::flatten(&self.toc)
     }
 
-    fn parse_toc(
-        &mut self,
-        version: &str,
-        toc_path: &String,
-        nav: &mut HashMap<String, (String, String)>,
-    ) -> Result<()> {
-        let xml = self.get_raw_text(&toc_path)?;
-        let doc = Document::parse(&xml)?;
+    /// Returns the index of the chapter and the offset into its text where the
+    /// table of contents entry starts.
+    pub fn toc_position(&mut self, entry: usize) -> Result<(usize, usize)> {
+        let entry = self.toc_entries()[entry];
+        let (index, fragment) = (entry.chapter, entry.fragment.clone());
+        self.target_position(index, fragment.as_deref())
+    }
 
-        if version == "3.0" {
-            if let Some(ol) = doc
-                .descendants()
-                .find(|n| n.has_tag_name("nav"))
-                .and_then(|n| n.children().find(|n| n.has_tag_name("ol")))
-            {
-                ol.descendants()
-                    .filter(|n| n.has_tag_name("a"))
-                    .for_each(|n| {
-                        if let (Some(path), Some(text)) = (n.attribute("href"), n.text()) {
-                            let np = path.split("#").next().unwrap();
-                            nav.insert(np.to_string(), (path.to_string(), text.to_string()));
-                        }
-                    });
+    /// Returns where reading starts, the beginning of the body matter if the
+    /// book marks it or else of the first linear chapter.
+    pub fn start_position(&mut self) -> Result<(usize, usize)> {
+        let Some(landmark) = self.landmarks.iter().find(|l| l.kind == "bodymatter") else {
+            return Ok((self.chapters.iter().position(|c| c.linear).unwrap_or(0), 0));
+        };
+        let (index, fragment) = (landmark.chapter, landmark.fragment.clone());
+        self.target_position(index, fragment.as_deref())
+    }
+
+    /// Returns the manifest item with the id.
+    pub fn manifest_item(&self, id: &str) -> Option<&ManifestItem> {
+        self.manifest.iter().find(|item| item.id == id)
+    }
+
+    /// Returns the manifest item of the cover image.
+    pub fn cover_image(&self) -> Option<&ManifestItem> {
+        let is_image = |item: &&ManifestItem| item.media_type.starts_with("image/");
+        let cover_id = self.metadata.as_ref().and_then(|m| m.cover.as_deref());
+        self.manifest
+            .iter()
+            .filter(is_image)
+            .find(|item| item.has_property("cover-image"))
+            .or_else(|| self.manifest_item(cover_id?).filter(is_image))
+    }
+
+    /// Returns the cover image, from the manifest or else the first image of
+    /// the cover page.
+    pub fn cover(&mut self) -> Result<Option<Cover>> {
+        let image = match self.cover_image() {
+            Some(item) => Some((item.path.clone(), item.media_type.clone())),
+            None => self.cover_page_image()?,
+        };
+        let Some((path, media_type)) = image else {
+            return Ok(None);
+        };
+        let data = self.get_raw_bytes(&path)?;
+        Ok(Some(Cover { data, media_type }))
+    }
+
+    // Finds the path and media type of the first image on the cover page of
+    // the landmarks.
+    fn cover_page_image(&mut self) -> Result<Option<(String, String)>> {
+        let Some(landmark) = self.landmarks.iter().find(|l| l.kind == "cover") else {
+            return Ok(None);
+        };
+        let page = self.chapters[landmark.chapter].path.clone();
+        let xml = self.get_raw_text(&page)?;
+        let doc = parse_xhtml(&xml)?;
+        let src = doc.descendants().find_map(|n| match n.tag_name().name() {
+            "img" => n.attribute("src"),
+            // An SVG image, as written by some tools to scale the cover.
+            "image" => n.attribute((NS_XLINK, "href")).or(n.attribute("href")),
+            _ => None,
+        });
+        let Some(path) = src.and_then(|src| href::resolve(&page, src)) else {
+            return Ok(None);
+        };
+        let media_type = match self.manifest.iter().find(|item| item.path == path) {
+            Some(item) => item.media_type.clone(),
+            None => image_media_type(&path).to_string(),
+        };
+        Ok(Some((path, media_type)))
+    }
+
+    /// Returns the manifest items of the CSS stylesheets.
+    pub fn stylesheets(&self) -> impl Iterator<Item = &ManifestItem> {
+        self.manifest
+            .iter()
+            .filter(|item| item.media_type == "text/css")
+    }
+
+    // Follows the fallback chain from `item` to the first XHTML document.
+    fn readable_item<'a>(&'a self, mut item: &'a ManifestItem) -> Option<&'a ManifestItem> {
+        // The chain is at most as long as the manifest, unless it loops.
+        for _ in 0..self.manifest.len() {
+            if item.is_xhtml() {
+                return Some(item);
             }
+            item = self.manifest_item(item.fallback.as_deref()?)?;
+        }
+        None
+    }
+
+    /// Returns the linear chapter after the one at `index`, or before it if
+    /// `forward` is not set, skipping the chapters outside the reading order.
+    pub fn adjacent_chapter(&self, index: usize, forward: bool) -> Option<usize> {
+        if forward {
+            (index + 1..self.chapters.len()).find(|&i| self.chapters[i].linear)
         } else {
-            if let Some(nav_map) = doc.descendants().find(|n| n.has_tag_name("navMap")) {
-                nav_map
-                    .descendants()
-                    .filter(|n| n.has_tag_name("navPoint"))
-                    .for_each(|n| {
-                        if let (Some(path), Some(text)) = (
-                            n.descendants()
-                                .find(|n| n.has_tag_name("content"))
-                                .and_then(|n| n.attribute("src")),
-                            n.descendants()
-                                .find(|n| n.has_tag_name("text"))
-                                .and_then(|n| n.text()),
-                        ) {
-                            let np = path.split("#").next().unwrap();
-                            nav.insert(np.to_string(), (path.to_string(), text.to_string()));
-                        }
-                    });
-            }
+            (0..index).rev().find(|&i| self.chapters[i].linear)
         }
-        Ok(())
+    }
+
+    /// Returns where the page of the print edition with the label starts.
+    pub fn page_position(&mut self, label: &str) -> Result<Option<(usize, usize)>> {
+        let Some(page) = self
+            .page_list
+            .iter()
+            .find(|p| p.label.eq_ignore_ascii_case(label))
+        else {
+            return Ok(None);
+        };
+        let (index, fragment) = (page.chapter, page.fragment.clone());
+        self.target_position(index, fragment.as_deref()).map(Some)
+    }
+
+    // Returns the chapter index and offset of the element with the id.
+    fn target_position(&mut self, index: usize, fragment: Option<&str>) -> Result<(usize, usize)> {
+        self.read_chapter(index)?;
+        let offset = fragment
+            .and_then(|id| self.chapters[index].id_offset(id))
+            .unwrap_or(0);
+        Ok((index, offset))
+    }
+
+    /// Resolves a link in the chapter at index `from` to the index of the
+    /// chapter it points to and an offset into its text. Returns `None` for
+    /// links outside of the spine.
+    pub fn resolve_link(&mut self, from: usize, href: &str) -> Result<Option<(usize, usize)>> {
+        let Some((index, fragment)) = self.link_target(from, href) else {
+            return Ok(None);
+        };
+
+        self.read_chapter(index)?;
+        let offset = fragment
+            .and_then(|id| self.chapters[index].id_offset(&id))
+            .unwrap_or(0);
+        Ok(Some((index, offset)))
+    }
+
+    /// Resolves a link in the chapter at index `from` to the index of a
+    /// chapter and of the footnote in it the link points to.
+    pub fn resolve_note(&mut self, from: usize, href: &str) -> Result<Option<(usize, usize)>> {
+        let Some((index, Some(id))) = self.link_target(from, href) else {
+            return Ok(None);
+        };
+
+        self.read_chapter(index)?;
+        Ok(self.chapters[index]
+            .note_index(&id)
+            .map(|note| (index, note)))
+    }
+
+    // Splits a link into the index of the chapter it points to and its fragment.
+    fn link_target(&self, from: usize, target: &str) -> Option<(usize, Option<String>)> {
+        let path = href::resolve(&self.chapters[from].path, target)?;
+        let fragment = href::split_fragment(target).1.map(href::percent_decode);
+        self.chapters
+            .iter()
+            .position(|c| c.path == path)
+            .map(|index| (index, fragment))
     }
 
     pub fn read_chapter(&mut self, index: usize) -> Result<&String> {
@@ -313,15 +807,424 @@ impl Epub {
             return Ok(&self.chapters[index].text);
         }
 
-        let relative_path = self.chapters[index].relative_path.clone();
-        let xml = self.get_raw_text(&format!("{}{}", &self.root_dir, relative_path))?;
+        let path = self.chapters[index].path.clone();
+        let xml = self.get_raw_text(&path)?;
 
-        let doc = Document::parse(&xml)?;
-        let body = doc.root_element().last_element_child().unwrap();
+        let doc = parse_xhtml(&xml)?;
+        // An empty document is read as an empty chapter.
+        let root = doc.root_element();
+        let body = root.last_element_child().unwrap_or(root);
 
         self.chapters[index].parse(body);
+        self.chapters[index].warnings = doc.warnings().to_vec();
         self.chapters[index].is_parsed = true;
 
         Ok(&self.chapters[index].text)
     }
 }
+
+// Content documents may use the entities of HTML as XHTML, and are read as
+// far as possible when they are malformed.
+const XHTML_OPTIONS: ParsingOptions = ParsingOptions {
+    html_entities: true,
+    recover: true,
+};
+
+fn parse_xhtml(xml: &str) -> Result<Document<'_>> {
+    Ok(Document::parse_with_options(xml, XHTML_OPTIONS)?)
+}
+
+// Package documents and NCX files may use the entities of HTML as well, like
+// `&nbsp;` in a title, but are expected to be well-formed.
+const PACKAGE_OPTIONS: ParsingOptions = ParsingOptions {
+    html_entities: true,
+    recover: false,
+};
+
+fn parse_package(xml: &str) -> Result<Document<'_>> {
+    Ok(Document::parse_with_options(xml, PACKAGE_OPTIONS)?)
+}
+
+// Guesses the media type of an image from the extension of its file name.
+pub fn image_media_type(path: &str) -> &'static str {
+    let extension = path.rsplit_once('.').map(|(_, e)| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("webp") => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+// Element children of `node` named `name` in the OPF namespace, or without a
+// namespace as some books leave it out.
+fn opf_children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children().filter(move |n| {
+        n.is_element()
+            && n.tag_name().name() == name
+            && matches!(n.tag_name().namespace(), None | Some(NS_OPF))
+    })
+}
+
+// Guesses the scheme of an identifier from its URN prefix, or from its
+// digits for a bare ISBN.
+fn identifier_scheme(value: &str) -> Option<String> {
+    if let Some(rest) = value.strip_prefix("urn:") {
+        let (scheme, _) = rest.split_once(':')?;
+        return Some(scheme.to_ascii_uppercase());
+    }
+    is_isbn(value).then(|| "ISBN".to_string())
+}
+
+// Whether the value is an ISBN-10 or ISBN-13 with a valid check digit,
+// ignoring hyphens and spaces.
+fn is_isbn(value: &str) -> bool {
+    let digits: Vec<char> = value.chars().filter(|c| !matches!(c, '-' | ' ')).collect();
+    let digit = |c: &char| c.to_digit(10);
+    match digits.len() {
+        10 => {
+            let check = match digits[9] {
+                'X' | 'x' => Some(10),
+                c => digit(&c),
+            };
+            let sum = digits[..9]
+                .iter()
+                .map(digit)
+                .chain([check])
+                .enumerate()
+                .try_fold(0, |sum, (i, d)| Some(sum + (10 - i as u32) * d?));
+            sum.is_some_and(|sum| sum % 11 == 0)
+        }
+        13 if digits.starts_with(&['9', '7']) => {
+            let sum = digits
+                .iter()
+                .map(digit)
+                .enumerate()
+                .try_fold(0, |sum, (i, d)| Some(sum + [1, 3][i % 2] * d?));
+            sum.is_some_and(|sum| sum % 10 == 0)
+        }
+        _ => false,
+    }
+}
+
+// Names the identifier types of ONIX code list 5 which are used in books.
+fn onix_identifier_type(code: &str) -> Option<&'static str> {
+    match code {
+        "02" | "15" => Some("ISBN"),
+        "06" => Some("DOI"),
+        "22" => Some("URN"),
+        _ => None,
+    }
+}
+
+/// Whether the `epub:type` of the element contains any of `types`.
+pub fn has_epub_type(n: Node, types: &[&str]) -> bool {
+    n.attribute((NS_OPS, "type"))
+        .is_some_and(|value| value.split_ascii_whitespace().any(|t| types.contains(&t)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use std::io::Write;
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    const CONTAINER: &str = r#"<?xml version="1.0"?>
+<container xmlns="urn:oasis:names:tc:opendocument:xmlns:container" version="1.0">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>"#;
+
+    // Zips the files of a book with its mimetype and container.xml.
+    fn book(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let container = [
+            ("mimetype", EPUB_MIME_TYPE.as_bytes()),
+            ("META-INF/container.xml", CONTAINER.as_bytes()),
+        ];
+        for (name, data) in container.iter().chain(files) {
+            zip.start_file(name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    // Writes a package document, with the parts given inside its sections.
+    fn package(metadata: &str, manifest: &str, spine: &str) -> String {
+        format!(
+            r#"<package xmlns="http://www.idpf.org/2007/opf" version="3.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">{}</metadata>
+  <manifest>{}</manifest>
+  <spine>{}</spine>
+</package>"#,
+            metadata, manifest, spine
+        )
+    }
+
+    // Opens a book of the package parts and of the files in `OEBPS/`.
+    fn open_book(
+        metadata: &str,
+        manifest: &str,
+        spine: &str,
+        files: &[(&str, &str)],
+    ) -> Epub<Cursor<Vec<u8>>> {
+        let opf = package(metadata, manifest, spine);
+        let paths: Vec<String> = files
+            .iter()
+            .map(|(name, _)| format!("OEBPS/{}", name))
+            .collect();
+        let mut entries: Vec<(&str, &[u8])> = vec![("OEBPS/content.opf", opf.as_bytes())];
+        for (path, (_, data)) in paths.iter().zip(files) {
+            entries.push((path, data.as_bytes()));
+        }
+        Epub::from_bytes(book(&entries)).unwrap()
+    }
+
+    // Opens a book with a chapter of each content document, in order.
+    fn book_of_chapters(chapters: &[&str]) -> Epub<Cursor<Vec<u8>>> {
+        let mut manifest = String::new();
+        let mut spine = String::new();
+        let names: Vec<String> = (0..chapters.len())
+            .map(|i| format!("c{}.xhtml", i))
+            .collect();
+        for (i, name) in names.iter().enumerate() {
+            manifest += &format!(
+                r#"<item id="c{}" href="{}" media-type="application/xhtml+xml"/>"#,
+                i, name
+            );
+            spine += &format!(r#"<itemref idref="c{}"/>"#, i);
+        }
+        let files: Vec<(&str, &str)> = names
+            .iter()
+            .map(String::as_str)
+            .zip(chapters.iter().copied())
+            .collect();
+        open_book("", &manifest, &spine, &files)
+    }
+
+    #[test]
+    fn empty_chapter() {
+        let mut ebook = book_of_chapters(&[r#"<html xmlns="http://www.w3.org/1999/xhtml"/>"#]);
+        assert_eq!(ebook.read_chapter(0).unwrap(), "");
+    }
+
+    #[test]
+    fn invalid_characters_in_attributes() {
+        let chapter = "<html xmlns='http://www.w3.org/1999/xhtml'><body>\
+            <p title='a\u{1}b'>Text</p></body></html>";
+        let mut ebook = book_of_chapters(&[chapter]);
+        assert_eq!(ebook.read_chapter(0).unwrap().trim(), "Text");
+        let warnings = &ebook.chapters[0].warnings;
+        assert!(matches!(warnings[..], [xml::Error::NonXmlChar('\u{1}', _)]));
+    }
+
+    const CHAPTER: &str = r#"<html xmlns="http://www.w3.org/1999/xhtml"><body>
+<h1 id="title">Title</h1><p id="start">Text</p><p id="p1">More text</p>
+</body></html>"#;
+
+    const NAV_AND_NCX: &str = r#"
+<item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+<item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml"/>
+<item id="c0" href="c0.xhtml" media-type="application/xhtml+xml"/>
+<item id="c1" href="c1.xhtml" media-type="application/xhtml+xml"/>"#;
+
+    #[test]
+    fn ncx_toc_keeps_nav_page_list() {
+        let nav = r#"<html xmlns="http://www.w3.org/1999/xhtml"
+    xmlns:epub="http://www.idpf.org/2007/ops"><body>
+<nav epub:type="toc"><ol/></nav>
+<nav epub:type="page-list"><ol><li><a href="c1.xhtml#p1">7</a></li></ol></nav>
+</body></html>"#;
+        let ncx = r#"<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/">
+<navMap><navPoint><navLabel><text>One</text></navLabel><content src="c0.xhtml"/></navPoint></navMap>
+<pageList><pageTarget value="1"><content src="c0.xhtml"/></pageTarget></pageList>
+</ncx>"#;
+        let spine = r#"<itemref idref="c0"/><itemref idref="c1"/>"#;
+        let files = [
+            ("nav.xhtml", nav),
+            ("toc.ncx", ncx),
+            ("c0.xhtml", CHAPTER),
+            ("c1.xhtml", CHAPTER),
+        ];
+        let ebook = open_book("", NAV_AND_NCX, spine, &files);
+        assert_eq!(ebook.toc_entries()[0].title, "One");
+        let pages: Vec<_> = ebook
+            .page_list
+            .iter()
+            .map(|p| (p.label.as_str(), p.chapter))
+            .collect();
+        assert_eq!(pages, [("7", 1)]);
+
+        // Without a page list in the navigation document, the NCX's is used.
+        let nav = r#"<html xmlns="http://www.w3.org/1999/xhtml"><body/></html>"#;
+        let files = [("nav.xhtml", nav), files[1], files[2], files[3]];
+        let ebook = open_book("", NAV_AND_NCX, spine, &files);
+        let pages: Vec<_> = ebook
+            .page_list
+            .iter()
+            .map(|p| (p.label.as_str(), p.chapter))
+            .collect();
+        assert_eq!(pages, [("1", 0)]);
+    }
+
+    #[test]
+    fn starts_at_body_matter() {
+        let nav = r#"<html xmlns="http://www.w3.org/1999/xhtml"
+    xmlns:epub="http://www.idpf.org/2007/ops"><body>
+<nav epub:type="toc"><ol><li><a href="c0.xhtml">Cover</a></li></ol></nav>
+<nav epub:type="landmarks"><ol>
+  <li><a epub:type="bodymatter" href="c1.xhtml#start">Start</a></li>
+</ol></nav>
+</body></html>"#;
+        let spine = r#"<itemref idref="c0"/><itemref idref="c1"/>"#;
+        let files = [
+            ("nav.xhtml", nav),
+            ("toc.ncx", "<ncx/>"),
+            ("c0.xhtml", CHAPTER),
+            ("c1.xhtml", CHAPTER),
+        ];
+        let mut ebook = open_book("", NAV_AND_NCX, spine, &files);
+        let (index, offset) = ebook.start_position().unwrap();
+        assert_eq!(index, 1);
+        assert!(ebook.chapters[1].text[offset..].starts_with("Text"));
+
+        // Without landmarks, reading starts at the first linear chapter.
+        let spine = r#"<itemref idref="c0" linear="no"/><itemref idref="c1"/>"#;
+        let files = [("nav.xhtml", CHAPTER), files[1], files[2], files[3]];
+        let mut ebook = open_book("", NAV_AND_NCX, spine, &files);
+        assert_eq!(ebook.start_position().unwrap(), (1, 0));
+    }
+
+    #[test]
+    fn identifier_schemes() {
+        let metadata = r##"
+<dc:identifier id="isbn">9780000000002</dc:identifier>
+<meta refines="#isbn" property="identifier-type" scheme="onix:codelist5">15</meta>
+<dc:identifier id="doi">10.1000/182</dc:identifier>
+<meta refines="#doi" property="identifier-type" scheme="onix:codelist5">06</meta>
+<dc:identifier id="other">123</dc:identifier>
+<meta refines="#other" property="identifier-type" scheme="onix:codelist5">01</meta>
+<dc:identifier>0-306-40615-2</dc:identifier>
+<dc:identifier>978-0-306-40615-7</dc:identifier>
+<dc:identifier>9780306406158</dc:identifier>
+<dc:identifier>urn:uuid:0000</dc:identifier>"##;
+        let manifest = r#"<item id="c0" href="c0.xhtml" media-type="application/xhtml+xml"/>"#;
+        let spine = r#"<itemref idref="c0"/>"#;
+        let ebook = open_book(metadata, manifest, spine, &[("c0.xhtml", CHAPTER)]);
+        let identifiers = &ebook.metadata.as_ref().unwrap().identifiers;
+        let schemes: Vec<_> = identifiers.iter().map(|i| i.scheme.as_deref()).collect();
+        assert_eq!(
+            schemes,
+            [
+                Some("ISBN"),
+                Some("DOI"),
+                Some("01"),
+                Some("ISBN"),
+                Some("ISBN"),
+                None,
+                Some("UUID")
+            ]
+        );
+    }
+
+    #[test]
+    fn cover_image_is_an_image() {
+        let metadata = r#"<meta name="cover" content="img"/>"#;
+        let manifest = r#"
+<item id="page" href="c0.xhtml" media-type="application/xhtml+xml" properties="cover-image"/>
+<item id="img" href="cover.jpg" media-type="image/jpeg"/>"#;
+        let spine = r#"<itemref idref="page"/>"#;
+        let ebook = open_book(metadata, manifest, spine, &[("c0.xhtml", CHAPTER)]);
+        let cover = ebook.cover_image().map(|item| item.id.as_str());
+        assert_eq!(cover, Some("img"));
+
+        let metadata = r#"<meta name="cover" content="page"/>"#;
+        let ebook = open_book(metadata, manifest, spine, &[("c0.xhtml", CHAPTER)]);
+        assert!(ebook.cover_image().is_none());
+    }
+
+    #[test]
+    fn manifest_href_outside_the_book() {
+        let manifest = r#"
+<item id="c0" href="../../c0.xhtml" media-type="application/xhtml+xml"/>
+<item id="font" href="https://example.com/font.otf" media-type="font/otf"/>"#;
+        let opf = package("", manifest, r#"<itemref idref="c0"/>"#);
+        let data = book(&[("OEBPS/content.opf", opf.as_bytes())]);
+        let Err(Error::ParseError(message)) = Epub::from_bytes(data) else {
+            panic!("expected a parse error");
+        };
+        let expected = "Manifest <item> c0 has an href ../../c0.xhtml outside the book";
+        assert_eq!(message, expected);
+
+        // Remote resources are left out of the manifest.
+        let manifest = r#"
+<item id="c0" href="c0.xhtml" media-type="application/xhtml+xml"/>
+<item id="font" href="https://example.com/font.otf" media-type="font/otf"/>"#;
+        let spine = r#"<itemref idref="c0"/>"#;
+        let ebook = open_book("", manifest, spine, &[("c0.xhtml", CHAPTER)]);
+        assert!(ebook.manifest_item("font").is_none());
+    }
+
+    #[test]
+    fn utf16_package() {
+        let opf = r#"<?xml version="1.0" encoding="UTF-16"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="2.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:title>Café</dc:title></metadata>
+  <manifest><item id="c1" href="c1.xhtml" media-type="application/xhtml+xml"/></manifest>
+  <spine><itemref idref="c1"/></spine>
+</package>"#;
+        let mut data = vec![0xFF, 0xFE];
+        data.extend(opf.encode_utf16().flat_map(u16::to_le_bytes));
+        let chapter = b"<?xml version='1.0' encoding='windows-1252'?>\
+            <html xmlns='http://www.w3.org/1999/xhtml'><body><p>\x93Caf\xE9\x94</p></body></html>";
+        let mut ebook = Epub::from_bytes(book(&[
+            ("OEBPS/content.opf", &data),
+            ("OEBPS/c1.xhtml", chapter),
+        ]))
+        .unwrap();
+        assert_eq!(ebook.metadata.as_ref().unwrap().title(), Some("Café"));
+        let text = ebook.read_chapter(0).unwrap();
+        assert_eq!(text.trim(), "\u{201C}Café\u{201D}");
+    }
+
+    #[test]
+    fn package_with_html_entities() {
+        let opf = r#"<?xml version="1.0"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="2.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:title>Title&nbsp;&mdash;&nbsp;Subtitle</dc:title>
+  </metadata>
+  <manifest>
+    <item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml"/>
+    <item id="c1" href="c1.xhtml" media-type="application/xhtml+xml"/>
+  </manifest>
+  <spine toc="ncx"><itemref idref="c1"/></spine>
+</package>"#;
+        let ncx = r#"<?xml version="1.0"?>
+<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1">
+  <navMap>
+    <navPoint id="p1">
+      <navLabel><text>Chapter&nbsp;1</text></navLabel>
+      <content src="c1.xhtml"/>
+    </navPoint>
+  </navMap>
+</ncx>"#;
+        let chapter = r#"<html xmlns="http://www.w3.org/1999/xhtml"><body/></html>"#;
+        let ebook = Epub::from_bytes(book(&[
+            ("OEBPS/content.opf", opf.as_bytes()),
+            ("OEBPS/toc.ncx", ncx.as_bytes()),
+            ("OEBPS/c1.xhtml", chapter.as_bytes()),
+        ]))
+        .unwrap();
+        let metadata = ebook.metadata.as_ref().unwrap();
+        assert_eq!(metadata.title(), Some("Title\u{a0}\u{2014}\u{a0}Subtitle"));
+        assert_eq!(ebook.toc_entries()[0].title, "Chapter 1");
+    }
+}
@@ -5,6 +5,7 @@ use super::xml;
 
 /// `Error` provides an enumeration of all possible errors reported by Cauldron.
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     /// File Not Found On Path Provided.
     FileNotFound(String),
@@ -17,9 +18,9 @@ pub enum Error {
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            &Error::FileNotFound(ref path) => write!(f, "File Not Found on path: {}", path),
-            &Error::ParseError(ref msg) => write!(f, "Malformed epub encountered: {}", msg),
-            &Error::Unsupported(ref msg) => write!(f, "{}", msg),
+            Error::FileNotFound(path) => write!(f, "File Not Found on path: {}", path),
+            Error::ParseError(msg) => write!(f, "Malformed epub encountered: {}", msg),
+            Error::Unsupported(msg) => write!(f, "{}", msg),
         }
     }
 }
@@ -36,19 +37,19 @@ impl std::error::Error for Error {
 
 impl From<xml::Error> for Error {
     fn from(err: xml::Error) -> Self {
-        Error::ParseError(format!("Invalid Epub Format: {}", err.to_string()))
+        Error::ParseError(format!("Invalid Epub Format: {}", err))
     }
 }
 
 impl From<zip::result::ZipError> for Error {
     fn from(err: zip::result::ZipError) -> Self {
-        Error::ParseError(format!("Invalid Epub Format: {}", err.to_string()))
+        Error::ParseError(format!("Invalid Epub Format: {}", err))
     }
 }
 
 impl From<std::io::Error> for Error {
     fn from(err: std::io::Error) -> Self {
-        Error::ParseError(format!("Unable to display: {}", err.to_string()))
+        Error::ParseError(format!("Unable to display: {}", err))
     }
 }
 
@@ -62,6 +63,11 @@ pub fn to_parse_error() -> Error {
     Error::ParseError("Invalid Epub Format".to_string())
 }
 
+/// function to create a parse error with a message
+pub fn parse_error(message: &str) -> Error {
+    Error::ParseError(message.to_string())
+}
+
 
 // /// function to create an unsupported codec error.
 // pub fn unsupported_error<T>(msg: &'static str) -> Result<T> {
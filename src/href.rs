@@ -0,0 +1,160 @@
+//! The `href` module resolves hrefs between files of the container.
+//!
+//! Paths are the names of entries in the ZIP archive, like
+//! `OEBPS/Text/ch1.xhtml`. Hrefs are relative URLs which may contain dot
+//! segments, percent-escapes, a query and a fragment.
+
+/// Splits an href into its path and its fragment, without the `#`.
+pub fn split_fragment(href: &str) -> (&str, Option<&str>) {
+    match href.split_once('#') {
+        Some((path, fragment)) => (path, Some(fragment)),
+        None => (href, None),
+    }
+}
+
+/// Resolves `href` against the directory of the file at `base` and returns
+/// the path it points to, without its fragment. An empty path refers to
+/// `base` itself. Returns `None` for absolute URLs like `https://...`, and
+/// for paths going above the root of the container.
+pub fn resolve(base: &str, href: &str) -> Option<String> {
+    let (path, _) = split_fragment(href);
+    let path = path.split('?').next().unwrap_or_default();
+    if has_scheme(path) {
+        return None;
+    }
+    if path.is_empty() {
+        return Some(base.to_string());
+    }
+
+    let mut segments: Vec<String> = Vec::new();
+    if !path.starts_with('/') {
+        segments.extend(base.split('/').map(String::from));
+        segments.pop();
+    }
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            _ => segments.push(percent_decode(segment)),
+        }
+    }
+    Some(segments.join("/"))
+}
+
+/// Decodes `%XX` escapes. Invalid escapes are kept as they are, and
+/// invalid UTF-8 is replaced.
+pub fn percent_decode(text: &str) -> String {
+    if !text.contains('%') {
+        return text.to_string();
+    }
+
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = match bytes.get(i + 1..i + 3) {
+            Some(&[hi, lo]) if bytes[i] == b'%' => hex_value(hi).zip(hex_value(lo)),
+            _ => None,
+        };
+        match escape {
+            Some((hi, lo)) => {
+                decoded.push(hi << 4 | lo);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Whether the href starts with a URL scheme like `http:` or `mailto:`.
+pub fn has_scheme(href: &str) -> bool {
+    match href.split_once(':') {
+        Some((scheme, _)) => {
+            let mut chars = scheme.chars();
+            chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+                && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "OEBPS/Text/c1.xhtml";
+
+    fn assert_resolves(cases: &[(&str, Option<&str>)]) {
+        for &(href, path) in cases {
+            assert_eq!(resolve(BASE, href).as_deref(), path, "{}", href);
+        }
+    }
+
+    #[test]
+    fn resolves_against_base() {
+        assert_resolves(&[
+            ("c2.xhtml", Some("OEBPS/Text/c2.xhtml")),
+            ("./c2.xhtml", Some("OEBPS/Text/c2.xhtml")),
+            ("../Images/a.png", Some("OEBPS/Images/a.png")),
+            ("a/./b/../c.xhtml", Some("OEBPS/Text/a/c.xhtml")),
+            ("a//b.xhtml", Some("OEBPS/Text/a/b.xhtml")),
+            ("/c2.xhtml", Some("c2.xhtml")),
+            ("../../c2.xhtml", Some("c2.xhtml")),
+        ]);
+        assert_eq!(
+            resolve("content.opf", "c1.xhtml").as_deref(),
+            Some("c1.xhtml")
+        );
+    }
+
+    #[test]
+    fn strips_fragments_and_queries() {
+        assert_resolves(&[
+            ("c2.xhtml#note", Some("OEBPS/Text/c2.xhtml")),
+            ("c2.xhtml?a=b#note", Some("OEBPS/Text/c2.xhtml")),
+            ("#note", Some(BASE)),
+            ("", Some(BASE)),
+        ]);
+        assert_eq!(split_fragment("a.xhtml#b#c"), ("a.xhtml", Some("b#c")));
+        assert_eq!(split_fragment("a.xhtml"), ("a.xhtml", None));
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_resolves(&[
+            ("Chapter%201.xhtml", Some("OEBPS/Text/Chapter 1.xhtml")),
+            ("caf%C3%A9.xhtml", Some("OEBPS/Text/café.xhtml")),
+            ("a%2Fb.xhtml", Some("OEBPS/Text/a/b.xhtml")),
+            ("100%.xhtml", Some("OEBPS/Text/100%.xhtml")),
+        ]);
+        assert_eq!(percent_decode("%zz%4"), "%zz%4");
+        assert_eq!(percent_decode("%FF"), "\u{FFFD}");
+    }
+
+    #[test]
+    fn rejects_urls_and_paths_above_the_root() {
+        assert_resolves(&[
+            ("https://example.com/c2.xhtml", None),
+            ("mailto:someone@example.com", None),
+            ("../../../c2.xhtml", None),
+            ("a/../../../../c2.xhtml", None),
+            ("/../c2.xhtml", None),
+        ]);
+        assert_eq!(resolve("c1.xhtml", "../c2.xhtml"), None);
+    }
+}
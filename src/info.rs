@@ -0,0 +1,189 @@
+//! The `info` module prints the metadata of a book.
+
+use std::io::{Read, Seek};
+
+use super::epub::{Creator, Epub, Identifier, Metadata, Title};
+use super::layout::pad;
+
+/// Prints the metadata of the book, as JSON if `json` is set.
+pub fn print_info<R: Read + Seek>(ebook: &Epub<R>, json: bool) {
+    let empty = Metadata::default();
+    let metadata = ebook.metadata.as_ref().unwrap_or(&empty);
+    if json {
+        print_json(ebook, metadata);
+    } else {
+        print_text(ebook, metadata);
+    }
+}
+
+fn print_text<R: Read + Seek>(ebook: &Epub<R>, metadata: &Metadata) {
+    // Appends the details in parentheses, if there are any.
+    let detailed = |text: &str, details: &[&Option<String>]| {
+        let details: Vec<&str> = details.iter().filter_map(|d| d.as_deref()).collect();
+        match details[..] {
+            [] => text.to_string(),
+            _ => format!("{} ({})", text, details.join("; ")),
+        }
+    };
+    let title = |t: &Title| detailed(&t.text, &[&t.title_type]);
+    let creator = |c: &Creator| detailed(&c.name, &[&c.role, &c.file_as]);
+    let identifier = |i: &Identifier| detailed(&i.value, &[&i.scheme]);
+
+    let mut fields: Vec<(&str, String)> = Vec::new();
+    fields.extend(metadata.titles.iter().map(|t| ("Title", title(t))));
+    fields.extend(metadata.creators.iter().map(|c| ("Creator", creator(c))));
+    fields.extend(
+        metadata
+            .contributors
+            .iter()
+            .map(|c| ("Contributor", creator(c))),
+    );
+    fields.extend(metadata.languages.iter().map(|l| ("Language", l.clone())));
+    fields.extend(
+        metadata
+            .identifiers
+            .iter()
+            .map(|i| ("Identifier", identifier(i))),
+    );
+    fields.extend(metadata.subjects.iter().map(|s| ("Subject", s.clone())));
+    let series = metadata
+        .series
+        .as_ref()
+        .map(|series| match &metadata.series_index {
+            Some(index) => format!("{} #{}", series, index),
+            None => series.clone(),
+        });
+    let single = [
+        ("Series", &series),
+        ("Publisher", &metadata.publisher),
+        ("Date", &metadata.date),
+        ("Modified", &metadata.modified),
+        ("Rights", &metadata.rights),
+        ("Description", &metadata.description),
+    ];
+    for (label, value) in single {
+        if let Some(value) = value {
+            fields.push((label, value.clone()));
+        }
+    }
+    for landmark in &ebook.landmarks {
+        fields.push((
+            "Landmark",
+            format!("{} ({})", landmark.title, landmark.kind),
+        ));
+    }
+    if let Some(cover) = ebook.cover_image() {
+        fields.push(("Cover", format!("{} ({})", cover.path, cover.media_type)));
+    }
+    fields.extend(ebook.stylesheets().map(|s| ("Stylesheet", s.path.clone())));
+    fields.push(("EPUB version", ebook.version.clone()));
+    fields.push(("Resources", ebook.manifest.len().to_string()));
+    fields.push(("Spine length", ebook.chapters.len().to_string()));
+    fields.push(("TOC entries", ebook.toc_entries().len().to_string()));
+    if !ebook.page_list.is_empty() {
+        fields.push(("Print pages", ebook.page_list.len().to_string()));
+    }
+
+    for (label, value) in fields {
+        println!("{} {}", pad(&format!("{}:", label), 13), value);
+    }
+}
+
+fn print_json<R: Read + Seek>(ebook: &Epub<R>, metadata: &Metadata) {
+    let strings = |values: &[String]| {
+        let values: Vec<String> = values.iter().map(|v| json_string(v)).collect();
+        format!("[{}]", values.join(", "))
+    };
+    let objects = |objects: Vec<String>| format!("[{}]", objects.join(", "));
+    let titles = metadata.titles.iter().map(|t| {
+        format!(
+            "{{\"title\": {}, \"title_type\": {}}}",
+            json_string(&t.text),
+            json_option(&t.title_type)
+        )
+    });
+    let creators = |creators: &[Creator]| {
+        let creators = creators.iter().map(|c| {
+            format!(
+                "{{\"name\": {}, \"role\": {}, \"file_as\": {}}}",
+                json_string(&c.name),
+                json_option(&c.role),
+                json_option(&c.file_as)
+            )
+        });
+        objects(creators.collect())
+    };
+    let identifiers = metadata.identifiers.iter().map(|i| {
+        format!(
+            "{{\"value\": {}, \"scheme\": {}}}",
+            json_string(&i.value),
+            json_option(&i.scheme)
+        )
+    });
+    let landmarks = ebook.landmarks.iter().map(|l| {
+        format!(
+            "{{\"type\": {}, \"title\": {}}}",
+            json_string(&l.kind),
+            json_string(&l.title)
+        )
+    });
+    let stylesheets = ebook.stylesheets().map(|s| json_string(&s.path));
+
+    let fields = [
+        ("titles", objects(titles.collect())),
+        ("creators", creators(&metadata.creators)),
+        ("contributors", creators(&metadata.contributors)),
+        ("languages", strings(&metadata.languages)),
+        ("identifiers", objects(identifiers.collect())),
+        ("subjects", strings(&metadata.subjects)),
+        ("series", json_option(&metadata.series)),
+        ("series_index", json_option(&metadata.series_index)),
+        ("publisher", json_option(&metadata.publisher)),
+        ("date", json_option(&metadata.date)),
+        ("modified", json_option(&metadata.modified)),
+        ("rights", json_option(&metadata.rights)),
+        ("description", json_option(&metadata.description)),
+        ("landmarks", objects(landmarks.collect())),
+        (
+            "cover",
+            json_option(&ebook.cover_image().map(|c| c.path.clone())),
+        ),
+        ("stylesheets", objects(stylesheets.collect())),
+        ("version", json_string(&ebook.version)),
+        ("resources", ebook.manifest.len().to_string()),
+        ("spine_length", ebook.chapters.len().to_string()),
+        ("toc_size", ebook.toc_entries().len().to_string()),
+        ("page_count", ebook.page_list.len().to_string()),
+    ];
+
+    println!("{{");
+    for (i, (key, value)) in fields.iter().enumerate() {
+        let comma = if i + 1 < fields.len() { "," } else { "" };
+        println!("  \"{}\": {}{}", key, value, comma);
+    }
+    println!("}}");
+}
+
+fn json_option(value: &Option<String>) -> String {
+    match value {
+        Some(value) => json_string(value),
+        None => "null".to_string(),
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut result = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
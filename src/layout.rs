@@ -0,0 +1,306 @@
+//! The `layout` module word-wraps chapter text into lines of a fixed width.
+
+use core::ops::Range;
+
+use super::document::BlockKind;
+use super::epub::Chapter;
+
+/// A display line of a chapter.
+#[derive(Clone, Debug)]
+pub struct Line {
+    /// Byte range of the line in `Chapter.text`.
+    pub range: Range<usize>,
+    /// Index of the block the line belongs to, `None` for the blank line
+    /// between blocks.
+    pub block: Option<usize>,
+    /// Whether this is the first line of its block.
+    pub first: bool,
+}
+
+/// Chapter text broken into display lines.
+///
+/// Every line refers to a byte range of the chapter text, so a position in
+/// the text can be mapped back to a line after the width changes.
+#[derive(Debug, Default)]
+pub struct Layout {
+    pub lines: Vec<Line>,
+}
+
+/// Returns the text put in front of a line of the block.
+pub fn prefix(kind: BlockKind, first: bool) -> &'static str {
+    match kind {
+        BlockKind::ListItem if first => "- ",
+        BlockKind::ListItem | BlockKind::Preformatted => "  ",
+        _ => "",
+    }
+}
+
+impl Layout {
+    pub fn new(chapter: &Chapter, width: usize) -> Self {
+        let mut lines = Vec::new();
+        let mut ranges = Vec::new();
+
+        let mut previous = None;
+        for (i, block) in chapter.blocks.iter().enumerate() {
+            let tight = previous == Some(BlockKind::ListItem) && block.kind == BlockKind::ListItem;
+            if previous.is_some() && !tight {
+                lines.push(Line {
+                    range: block.range.start..block.range.start,
+                    block: None,
+                    first: false,
+                });
+            }
+            previous = Some(block.kind);
+
+            let width = width.saturating_sub(prefix(block.kind, false).len()).max(1);
+            let mut start = block.range.start;
+            for hard_line in chapter.text[block.range.clone()].split('\n') {
+                wrap_line(
+                    &chapter.text,
+                    start..start + hard_line.len(),
+                    width,
+                    &mut ranges,
+                );
+                start += hard_line.len() + 1;
+            }
+
+            for (j, range) in ranges.drain(..).enumerate() {
+                lines.push(Line {
+                    range,
+                    block: Some(i),
+                    first: j == 0,
+                });
+            }
+        }
+
+        Layout { lines }
+    }
+
+    /// Index of the line containing the byte `offset`.
+    pub fn line_at(&self, offset: usize) -> usize {
+        self.lines
+            .partition_point(|line| line.range.start <= offset)
+            .saturating_sub(1)
+    }
+}
+
+// Greedily breaks a line without newlines at spaces, or before wide characters,
+// which may be broken anywhere. Words longer than `width` are split.
+fn wrap_line(text: &str, range: Range<usize>, width: usize, lines: &mut Vec<Range<usize>>) {
+    let line = &text[range.clone()];
+    let mut start = range.start;
+    let mut used = 0;
+    // (end of the current line, start of the next line, width used before the next line)
+    let mut last_break: Option<(usize, usize, usize)> = None;
+
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let pos = range.start + i;
+
+        if c == '\x1b' {
+            skip_escape(&mut chars);
+            continue;
+        }
+
+        let w = char_width(c);
+        if c == ' ' {
+            if used + w > width {
+                // The space the line is broken at takes no room.
+                lines.push(start..pos);
+                start = pos + 1;
+                used = 0;
+                last_break = None;
+            } else {
+                used += w;
+                last_break = Some((pos, pos + 1, used));
+            }
+            continue;
+        }
+
+        if w == 2 && pos > start {
+            last_break = Some((pos, pos, used));
+        }
+
+        if used + w > width && used > 0 {
+            match last_break.take() {
+                Some((end, next, width_before)) => {
+                    lines.push(start..end);
+                    start = next;
+                    used -= width_before;
+                }
+                None => {
+                    lines.push(start..pos);
+                    start = pos;
+                    used = 0;
+                }
+            }
+        }
+        used += w;
+    }
+
+    lines.push(start..range.end);
+}
+
+// Skips the rest of an ANSI escape sequence, the `\x1b` is already consumed.
+fn skip_escape<I: Iterator<Item = (usize, char)>>(chars: &mut core::iter::Peekable<I>) {
+    if chars.next_if(|&(_, c)| c == '[').is_none() {
+        chars.next();
+        return;
+    }
+    for (_, c) in chars.by_ref() {
+        if ('\x40'..='\x7e').contains(&c) {
+            break;
+        }
+    }
+}
+
+/// Returns the number of terminal columns used by `text`, ignoring escape
+/// sequences.
+pub fn display_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((_, c)) = chars.next() {
+        if c == '\x1b' {
+            skip_escape(&mut chars);
+        } else {
+            width += char_width(c);
+        }
+    }
+    width
+}
+
+/// Shortens `text` to at most `width` columns, marking the cut with `…`.
+pub fn truncate(text: &str, width: usize) -> String {
+    if display_width(text) <= width {
+        return text.to_string();
+    }
+
+    let mut result = String::new();
+    let mut used = 1;
+    for c in text.chars() {
+        used += char_width(c);
+        if used > width {
+            break;
+        }
+        result.push(c);
+    }
+    result.push('…');
+    result
+}
+
+/// Pads `text` with spaces to `width` columns.
+pub fn pad(text: &str, width: usize) -> String {
+    let padding = width.saturating_sub(display_width(text));
+    format!("{}{}", text, " ".repeat(padding))
+}
+
+/// Returns the number of terminal columns used by the character.
+pub fn char_width(c: char) -> usize {
+    let c = c as u32;
+
+    // Check for ASCII first.
+    if c < 0x7F {
+        return (c >= 0x20) as usize;
+    }
+
+    // Control characters and combining marks.
+    if matches!(c,
+        0x00007F..=0x00009F
+        | 0x000300..=0x00036F
+        | 0x000483..=0x000489
+        | 0x000591..=0x0005BD
+        | 0x000610..=0x00061A
+        | 0x00064B..=0x00065F
+        | 0x000E31
+        | 0x000E34..=0x000E3A
+        | 0x001AB0..=0x001AFF
+        | 0x001DC0..=0x001DFF
+        | 0x00200B..=0x00200F
+        | 0x002028..=0x00202E
+        | 0x002060..=0x002064
+        | 0x0020D0..=0x0020FF
+        | 0x00FE00..=0x00FE0F
+        | 0x00FE20..=0x00FE2F
+        | 0x00FEFF)
+    {
+        return 0;
+    }
+
+    // East Asian wide and fullwidth characters.
+    if matches!(c,
+        0x001100..=0x00115F
+        | 0x002E80..=0x00303E
+        | 0x003041..=0x0033FF
+        | 0x003400..=0x004DBF
+        | 0x004E00..=0x009FFF
+        | 0x00A000..=0x00A4CF
+        | 0x00AC00..=0x00D7A3
+        | 0x00F900..=0x00FAFF
+        | 0x00FE30..=0x00FE4F
+        | 0x00FF00..=0x00FF60
+        | 0x00FFE0..=0x00FFE6
+        | 0x01F300..=0x01F64F
+        | 0x01F900..=0x01F9FF
+        | 0x020000..=0x02FFFD
+        | 0x030000..=0x03FFFD)
+    {
+        return 2;
+    }
+
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wrap(text: &str, width: usize) -> Vec<&str> {
+        let mut lines = Vec::new();
+        wrap_line(text, 0..text.len(), width, &mut lines);
+        lines.into_iter().map(|range| &text[range]).collect()
+    }
+
+    #[test]
+    fn breaks_at_spaces() {
+        assert_eq!(wrap("aaa bbb ccc", 7), ["aaa bbb", "ccc"]);
+        assert_eq!(wrap("aaa bbb ccc", 5), ["aaa", "bbb", "ccc"]);
+        assert_eq!(wrap("abcd efgh", 4), ["abcd", "efgh"]);
+        assert_eq!(wrap("aaa bbb", 80), ["aaa bbb"]);
+    }
+
+    #[test]
+    fn splits_long_words() {
+        assert_eq!(wrap("abcdefghij", 4), ["abcd", "efgh", "ij"]);
+        assert_eq!(wrap("aa abcdefgh", 4), ["aa", "abcd", "efgh"]);
+        assert_eq!(wrap("abc", 1), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn breaks_wide_characters_anywhere() {
+        assert_eq!(wrap("日本語です", 4), ["日本", "語で", "す"]);
+        assert_eq!(wrap("日本語", 5), ["日本", "語"]);
+    }
+
+    #[test]
+    fn escapes_take_no_room() {
+        assert_eq!(wrap("\x1b[1mab\x1b[0m cd", 2), ["\x1b[1mab\x1b[0m", "cd"]);
+        assert_eq!(display_width("\x1b[1m日a\x1b[0m"), 3);
+    }
+
+    #[test]
+    fn empty_input() {
+        assert_eq!(wrap("", 10), [""]);
+        assert_eq!(display_width(""), 0);
+        assert_eq!(pad("", 2), "  ");
+    }
+
+    #[test]
+    fn pads_and_truncates_to_columns() {
+        assert_eq!(pad("ab", 4), "ab  ");
+        assert_eq!(pad("日", 4), "日  ");
+        assert_eq!(pad("日本", 3), "日本");
+        assert_eq!(truncate("abcdef", 4), "abc…");
+        assert_eq!(truncate("abc", 4), "abc");
+        assert_eq!(truncate("日本語", 4), "日…");
+    }
+}
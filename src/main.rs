@@ -1,14 +1,25 @@
+mod encoding;
 mod error;
+mod href;
+mod info;
 mod xml;
+mod document;
+mod dump;
 mod epub;
+mod layout;
 mod reader;
+mod render;
+mod search;
+mod store;
+mod toc;
 
 use std::{fs::{self}, path, process::exit};
+use store::History;
 
 pub type Result<T> = std::result::Result<T, error::Error>;
 
 use std::fs::OpenOptions;
-use std::io::Write;
+use std::io::{Read, Seek, Write};
 use lazy_static::lazy_static;
 use std::sync::Mutex;
 
@@ -16,7 +27,6 @@ lazy_static! {
     static ref LOG_FILE: Mutex<std::fs::File> = Mutex::new(
         OpenOptions::new()
             .create(true)
-            .write(true)
             .append(true)
             .open("debug.log")
             .expect("Failed to open log file")
@@ -34,7 +44,7 @@ pub fn log(message: &str) {
 #[macro_export]
 macro_rules! log {
     ($($arg:tt)*) => {
-        crate::log(&format!($($arg)*));
+        $crate::log(&format!($($arg)*));
     };
 }
 
@@ -43,51 +53,187 @@ macro_rules! log {
 // #[argh(help_triggers("-h", "--help", "help"))]
 /// read a book
 struct Args {
+    /// path of the book, or `-- -` to read it from stdin
     #[argh(positional)]
     path: Option<String>,
 
-    /// print reading history
+    /// print reading history, or open entry N of it with `-r N`
     #[argh(switch, short = 'r')]
     history: bool,
 
     /// characters per line
     #[argh(option, short = 'w', default = "75")]
     width: u16,
+
+    /// write the book as plain text to stdout, the default when stdout is not
+    /// a terminal
+    #[argh(switch)]
+    dump: bool,
+
+    /// chapters to write, as N or FROM-TO counted from 1 in reading order
+    #[argh(option)]
+    chapters: Option<String>,
+
+    /// table of contents entry to write, counted from 1
+    #[argh(option)]
+    toc: Option<usize>,
+
+    /// keep text styles as escape codes when writing to stdout
+    #[argh(switch)]
+    color: bool,
+
+    #[argh(subcommand)]
+    command: Option<Command>,
 }
 
-fn get_ebook_path(path: Option<String>) -> Option<Result<path::PathBuf>> {
-    return match path {
-        None => {
-            // TODO: read from history
-            None
-        },
-        Some(actual_path) => {
-            Some(fs::canonicalize(&actual_path)
-                    .map_err(|_| error::to_fnf_error(actual_path))
-            )
-        },
-    }
+#[derive(argh::FromArgs)]
+#[argh(subcommand)]
+enum Command {
+    Info(InfoArgs),
+    Cover(CoverArgs),
+}
+
+#[derive(argh::FromArgs)]
+#[argh(subcommand, name = "info")]
+/// print the metadata of a book
+struct InfoArgs {
+    #[argh(positional)]
+    path: Option<String>,
+
+    /// print the metadata as JSON
+    #[argh(switch)]
+    json: bool,
+}
+
+#[derive(argh::FromArgs)]
+#[argh(subcommand, name = "cover")]
+/// write the cover image of a book
+struct CoverArgs {
+    #[argh(positional)]
+    path: Option<String>,
+
+    /// file to write the image to, stdout if not given
+    #[argh(option, short = 'o')]
+    output: Option<String>,
 }
 
+fn get_ebook_path(path: Option<String>, history: &History) -> Option<Result<path::PathBuf>> {
+    match path {
+        None => history.get(1).map(|entry| Ok(entry.path.clone())),
+        Some(actual_path) => Some(
+            fs::canonicalize(&actual_path).map_err(|_| error::to_fnf_error(actual_path)),
+        ),
+    }
+}
 
 fn main() -> Result<()> {
     lazy_static::initialize(&LOG_FILE);
 
     let args: Args = argh::from_env();
 
-    if args.history {
-        println!("TODO: Print history");
-        return Ok(());
+    let mut history = History::load();
+
+    let path = match &args.command {
+        Some(Command::Info(info)) => info.path.clone(),
+        Some(Command::Cover(cover)) => cover.path.clone(),
+        None => args.path.clone(),
+    };
+    if path.as_deref() == Some("-") {
+        let mut ebook = epub::Epub::from_stdin()?;
+        return run(&mut ebook, args, &mut history);
     }
 
-    let path = get_ebook_path(args.path);
+    let path = if args.history && args.command.is_none() {
+        match path.as_deref().map(str::parse::<usize>) {
+            None => {
+                history.print();
+                return Ok(());
+            }
+            Some(Ok(n)) => history.get(n).map(|entry| Ok(entry.path.clone())),
+            Some(Err(_)) => {
+                eprintln!("Expected a history entry number");
+                exit(1);
+            }
+        }
+    } else {
+        get_ebook_path(path, &history)
+    };
     if path.is_none() {
-        println!("No ebook provided or in history");
+        eprintln!("No ebook provided or in history");
         exit(1);
     }
     let mut ebook = epub::Epub::new(path.unwrap()?)?;
+    run(&mut ebook, args, &mut history)
+}
+
+// Runs the subcommand on the book, or else writes it out or opens the reader.
+fn run<R: Read + Seek>(ebook: &mut epub::Epub<R>, args: Args, history: &mut History) -> Result<()> {
+    match args.command {
+        Some(Command::Info(info)) => {
+            info::print_info(ebook, info.json);
+            return Ok(());
+        }
+        Some(Command::Cover(cover)) => {
+            if cover.output.is_none() && termion::is_tty(&std::io::stdout()) {
+                eprintln!("Expected a file to write the cover to with -o");
+                exit(1);
+            }
+            let Some(image) = ebook.cover()? else {
+                eprintln!("The book has no cover");
+                exit(1);
+            };
+            match cover.output {
+                Some(output) => {
+                    // The image is written as is, warn about a misleading file name.
+                    let named = epub::image_media_type(&output);
+                    if named != image.media_type && named != "application/octet-stream" {
+                        eprintln!("Writing a cover of type {} to {}", image.media_type, output);
+                    }
+                    fs::write(output, image.data)?;
+                }
+                None => std::io::stdout().write_all(&image.data)?,
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+
+    // The reader takes its keys from stdin, so a book read from there is written out.
+    let dump = args.dump || args.chapters.is_some() || args.toc.is_some();
+    if dump || ebook.file_path.is_none() || !termion::is_tty(&std::io::stdout()) {
+        let selection = match (args.chapters, args.toc) {
+            (Some(_), Some(_)) => {
+                eprintln!("Expected either --chapters or --toc, not both");
+                exit(1);
+            }
+            (Some(range), None) => match dump::Selection::parse_chapters(&range) {
+                Some(dump::Selection::Chapters(range)) if range.start < ebook.chapters.len() => {
+                    dump::Selection::Chapters(range)
+                }
+                Some(_) => {
+                    eprintln!("The book has {} chapters", ebook.chapters.len());
+                    exit(1);
+                }
+                None => {
+                    eprintln!("Expected a chapter range like 3 or 2-5");
+                    exit(1);
+                }
+            },
+            (None, Some(entry)) if (1..=ebook.toc_entries().len()).contains(&entry) => {
+                dump::Selection::TocEntry(entry - 1)
+            }
+            (None, Some(_)) => {
+                eprintln!("The table of contents has {} entries", ebook.toc_entries().len());
+                exit(1);
+            }
+            (None, None) => dump::Selection::All,
+        };
+        return dump::dump_ebook(ebook, selection, args.width, args.color);
+    }
 
-    reader::read_ebook(&mut ebook)?;
+    let state = reader::read_ebook(ebook, args.width)?;
+    history.record(ebook, state.progress);
+    history.save()?;
 
     // println!("{:?}", ebook.chapters);
     // println!("TOC: {:?}", ebook.toc);
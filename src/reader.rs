@@ -1,95 +1,973 @@
-use std::io::{stdin, stdout, Stdout, Write};
+use core::ops::Range;
+use std::io::{stdin, stdout, Read, Seek, Stdout, Write};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::Duration;
 
 use termion::event::Key;
 use termion::input::TermRead;
 use termion::raw::{IntoRawMode, RawTerminal};
 use termion::screen::{AlternateScreen, IntoAlternateScreen};
-use termion::{clear, cursor};
+use termion::{clear, cursor, style};
 
 use super::epub::{Chapter, Epub};
+use super::layout::{pad, truncate, Layout};
 use super::log;
+use super::render::{render_line, Mark, Overlay};
+use super::search::{Match, Search};
+use super::store::{Annotation, AnnotationKind, BookState};
 use super::Result;
 
-fn redraw(
-    screen: &mut AlternateScreen<RawTerminal<Stdout>>,
-    lines: &Vec<&str>,
+type Screen = AlternateScreen<RawTerminal<Stdout>>;
+
+enum Event {
+    Key(Key),
+    Resize((u16, u16)),
+}
+
+/// Text being typed in the status line.
+struct Prompt {
+    kind: PromptKind,
+    input: String,
+}
+
+enum PromptKind {
+    Search,
+    /// A page number of the print edition to go to.
+    Page,
+    /// The note of an annotation which is added once the prompt is submitted.
+    Note(Annotation),
+}
+
+/// Blocks of the chapter being chosen for a new annotation.
+struct BlockSelection {
+    kind: AnnotationKind,
+    /// Index into `Chapter.blocks` of the block under the cursor.
+    cursor: usize,
+    /// Index of the block the selection was extended from, if any.
+    anchor: Option<usize>,
+}
+
+impl BlockSelection {
+    // Indices of the first and last selected blocks.
+    fn blocks(&self) -> (usize, usize) {
+        let anchor = self.anchor.unwrap_or(self.cursor);
+        (anchor.min(self.cursor), anchor.max(self.cursor))
+    }
+}
+
+/// A footnote shown on top of the text.
+struct NotePopup {
+    chapter: usize,
+    /// Index into `Chapter.notes`.
+    index: usize,
     scroll: usize,
+}
+
+struct Reader<'a, R> {
+    ebook: &'a mut Epub<R>,
+    chapter: usize,
+    layout: Layout,
+    scroll: usize,
+    /// Maximum characters per line.
+    width: u16,
     size: (u16, u16),
-) -> Result<()> {
-    write!(screen, "{}{}", clear::All, cursor::Goto(1, 1))?;
+    /// Selected entry while the table of contents is shown.
+    toc: Option<usize>,
+    prompt: Option<Prompt>,
+    search: Option<Search>,
+    /// Matches of the search in the current chapter.
+    matches: Vec<Range<usize>>,
+    current_match: Option<Match>,
+    /// Shown in the status line until the next key press.
+    message: Option<String>,
+    /// Index into `Chapter.links` of the focused link.
+    focus: Option<usize>,
+    /// Positions to return to from followed links.
+    back: Vec<(usize, usize)>,
+    note: Option<NotePopup>,
+    annotations: Vec<Annotation>,
+    /// Selected entry while the list of annotations is shown.
+    annotation_list: Option<usize>,
+    selection: Option<BlockSelection>,
+}
+
+// Sends key presses and terminal size changes to the returned channel.
+fn spawn_events(size: (u16, u16)) -> Receiver<Event> {
+    let (sender, receiver) = channel();
+
+    let key_sender = sender.clone();
+    thread::spawn(move || {
+        for key in stdin().keys().map_while(|key| key.ok()) {
+            if key_sender.send(Event::Key(key)).is_err() {
+                break;
+            }
+        }
+    });
+
+    // There is no portable way to wait for SIGWINCH, poll the size instead.
+    thread::spawn(move || {
+        let mut last_size = size;
+        loop {
+            thread::sleep(Duration::from_millis(100));
+            let Ok(current_size) = termion::terminal_size() else {
+                continue;
+            };
+            if current_size != last_size {
+                last_size = current_size;
+                if sender.send(Event::Resize(current_size)).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    receiver
+}
+
+impl<'a, R: Read + Seek> Reader<'a, R> {
+    // Parses the chapter and drops the annotations which don't fit its text,
+    // as saved ones may come from another edition of the book.
+    fn read_chapter(&mut self, index: usize) -> Result<()> {
+        let text = self.ebook.read_chapter(index)?;
+        self.annotations.retain_mut(|a| {
+            if a.chapter != index {
+                return true;
+            }
+            a.range.end = a.range.end.min(text.len());
+            a.range.start <= a.range.end
+                && text.is_char_boundary(a.range.start)
+                && text.is_char_boundary(a.range.end)
+        });
+        Ok(())
+    }
+
+    fn open_chapter(&mut self, index: usize, offset: usize) -> Result<()> {
+        self.read_chapter(index)?;
+        self.chapter = index;
+        self.focus = None;
+        self.relayout();
+        self.matches = match &self.search {
+            Some(search) => search.find_all(self.text()),
+            None => Vec::new(),
+        };
+        self.scroll = self.layout.line_at(offset);
+        log!(
+            "Opened chapter {} with {} lines",
+            index,
+            self.layout.lines.len()
+        );
+        let chapter = &self.ebook.chapters[index];
+        for warning in &chapter.warnings {
+            log!("Malformed markup in {}: {}", chapter.path, warning);
+        }
+        Ok(())
+    }
+
+    fn column_width(&self) -> u16 {
+        self.width.min(self.size.0).max(1)
+    }
+
+    fn relayout(&mut self) {
+        let chapter = &self.ebook.chapters[self.chapter];
+        self.layout = Layout::new(chapter, self.column_width() as usize);
+    }
+
+    fn resize(&mut self, size: (u16, u16)) {
+        let offset = self.offset();
+        self.size = size;
+        self.relayout();
+        self.scroll = self.layout.line_at(offset);
+    }
+
+    // Offset into the chapter text of the first visible line.
+    fn offset(&self) -> usize {
+        self.layout
+            .lines
+            .get(self.scroll)
+            .map_or(0, |line| line.range.start)
+    }
+
+    // Number of text lines on the screen, the last row is the status line.
+    fn height(&self) -> usize {
+        self.size.1.saturating_sub(1).max(1) as usize
+    }
+
+    fn max_scroll(&self) -> usize {
+        self.layout.lines.len().saturating_sub(self.height())
+    }
+
+    // Byte range of the chapter text on the screen.
+    fn visible_range(&self) -> Range<usize> {
+        let lines = &self.layout.lines;
+        let end = (self.scroll + self.height()).min(lines.len());
+        match lines.get(self.scroll..end) {
+            Some([first, .., last]) => first.range.start..last.range.end,
+            Some([only]) => only.range.clone(),
+            _ => 0..0,
+        }
+    }
 
-    let (term_width, term_height) = size;
-    let mut y = 1;
-    for line in lines.iter().skip(scroll) {
-        write!(screen, "{}{}\r\n", cursor::Goto(1, y as u16), line)?;
-        
-        let line_height = (line.len() as u16 + term_width - 1) / term_width;
-        if y + line_height as usize > term_height as usize {
-            break;
+    fn scroll_to(&mut self, scroll: usize) {
+        self.scroll = scroll;
+        // Drop the focus once its link is scrolled off the screen.
+        let visible = self.visible_range();
+        let links = &self.ebook.chapters[self.chapter].links;
+        if let Some(i) = self.focus {
+            if links[i].range.end <= visible.start || links[i].range.start >= visible.end {
+                self.focus = None;
+            }
+        }
+    }
+
+    /// Moves the focus to the next or previous link on the screen.
+    fn focus_link(&mut self, forward: bool) {
+        let visible = self.visible_range();
+        let links = &self.ebook.chapters[self.chapter].links;
+        let on_screen: Vec<usize> = (0..links.len())
+            .filter(|&i| links[i].range.start >= visible.start && links[i].range.end <= visible.end)
+            .collect();
+
+        self.focus = match (self.focus, forward) {
+            (Some(focus), true) => on_screen.iter().find(|&&i| i > focus),
+            (Some(focus), false) => on_screen.iter().rfind(|&&i| i < focus),
+            (None, true) => on_screen.first(),
+            (None, false) => on_screen.last(),
+        }
+        .copied();
+    }
+
+    /// Follows the focused link, remembering the current position.
+    fn follow_link(&mut self) -> Result<()> {
+        let Some(focus) = self.focus else {
+            return Ok(());
+        };
+        let target = self.ebook.chapters[self.chapter].links[focus]
+            .target
+            .clone();
+
+        if let Some((chapter, index)) = self.ebook.resolve_note(self.chapter, &target)? {
+            self.note = Some(NotePopup {
+                chapter,
+                index,
+                scroll: 0,
+            });
+            return Ok(());
+        }
+        match self.ebook.resolve_link(self.chapter, &target)? {
+            Some((index, offset)) => {
+                self.back.push((self.chapter, self.offset()));
+                self.open_chapter(index, offset)?;
+            }
+            None => self.message = Some(format!("Not in this book: {}", target)),
+        }
+        Ok(())
+    }
+
+    /// Returns to the position before the last followed link.
+    fn go_back(&mut self) -> Result<()> {
+        if let Some((index, offset)) = self.back.pop() {
+            self.open_chapter(index, offset)?;
+        }
+        Ok(())
+    }
+
+    fn text(&self) -> &str {
+        &self.ebook.chapters[self.chapter].text
+    }
+
+    fn overlays(&self) -> Vec<Overlay> {
+        let highlights = self
+            .annotations
+            .iter()
+            .filter(|a| a.kind == AnnotationKind::Highlight && a.chapter == self.chapter)
+            .map(|a| Overlay {
+                range: a.range.clone(),
+                mark: Mark::Highlight,
+            });
+        let matches = self.matches.iter().map(|range| Overlay {
+            range: range.clone(),
+            mark: Mark::Match,
+        });
+        let mut overlays: Vec<Overlay> = highlights.chain(matches).collect();
+        if let Some(selection) = &self.selection {
+            let blocks = &self.ebook.chapters[self.chapter].blocks;
+            let (first, last) = selection.blocks();
+            overlays.push(Overlay {
+                range: blocks[first].range.start..blocks[last].range.end,
+                mark: Mark::Selection,
+            });
+        }
+        if let Some(m) = self
+            .current_match
+            .as_ref()
+            .filter(|m| m.chapter == self.chapter)
+        {
+            overlays.push(Overlay {
+                range: m.range.clone(),
+                mark: Mark::CurrentMatch,
+            });
+        }
+        if let Some(focus) = self.focus {
+            overlays.push(Overlay {
+                range: self.ebook.chapters[self.chapter].links[focus].range.clone(),
+                mark: Mark::Focus,
+            });
+        }
+        overlays
+    }
+
+    /// Starts searching for `query` from the top of the screen.
+    fn start_search(&mut self, query: &str) -> Result<()> {
+        if query.is_empty() {
+            return Ok(());
         }
-        y += line_height as usize;
+        let search = Search::new(query);
+        self.matches = search.find_all(self.text());
+        self.search = Some(search);
+        self.current_match = None;
+        self.find_match(true)
     }
 
-    screen.flush()?;
+    /// Jumps to the next or previous match of the search.
+    fn find_match(&mut self, forward: bool) -> Result<()> {
+        let Some(search) = &self.search else {
+            return Ok(());
+        };
 
+        // Continue from the current match while it is in this chapter.
+        let (chapter, from) = match &self.current_match {
+            Some(m) if m.chapter == self.chapter => (m.chapter, m.range.start),
+            _ => (self.chapter, self.offset()),
+        };
+        let found = if forward {
+            let offset = if self.current_match.is_some() {
+                from + 1
+            } else {
+                from
+            };
+            search.next(self.ebook, chapter, offset)?
+        } else {
+            search.prev(self.ebook, chapter, from)?
+        };
+
+        let Some(found) = found else {
+            self.message = Some(format!("Pattern not found: {}", search.query));
+            return Ok(());
+        };
+        let wrapped = if forward {
+            (found.chapter, found.range.start) < (chapter, from)
+        } else {
+            (found.chapter, found.range.start) >= (chapter, from)
+        };
+        if wrapped {
+            self.message = Some("Search wrapped around".to_string());
+        }
+
+        if found.chapter != self.chapter {
+            self.open_chapter(found.chapter, found.range.start)?;
+        } else {
+            let line = self.layout.line_at(found.range.start);
+            if line < self.scroll || line >= self.scroll + self.height() {
+                self.scroll_to(line.min(self.max_scroll()));
+            }
+        }
+        self.current_match = Some(found);
+        Ok(())
+    }
+
+    fn clear_search(&mut self) {
+        self.search = None;
+        self.matches.clear();
+        self.current_match = None;
+    }
+
+    /// Handles a key while a prompt is shown.
+    fn prompt_key(&mut self, key: Key) -> Result<()> {
+        let Some(prompt) = self.prompt.as_mut() else {
+            return Ok(());
+        };
+        match key {
+            Key::Char('\n') => {
+                let Some(prompt) = self.prompt.take() else {
+                    return Ok(());
+                };
+                match prompt.kind {
+                    PromptKind::Search => self.start_search(&prompt.input)?,
+                    PromptKind::Page => self.go_to_page(prompt.input.trim())?,
+                    PromptKind::Note(mut annotation) => {
+                        annotation.note = prompt.input;
+                        self.add_annotation(annotation);
+                    }
+                }
+            }
+            Key::Char(c) => prompt.input.push(c),
+            Key::Backspace if prompt.input.is_empty() => self.prompt = None,
+            Key::Backspace => {
+                prompt.input.pop();
+            }
+            Key::Esc => self.prompt = None,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Opens the page of the print edition with the label.
+    fn go_to_page(&mut self, label: &str) -> Result<()> {
+        match self.ebook.page_position(label)? {
+            Some((index, offset)) => {
+                self.back.push((self.chapter, self.offset()));
+                self.open_chapter(index, offset)?;
+            }
+            None => self.message = Some(format!("No page {}", label)),
+        }
+        Ok(())
+    }
+
+    /// Starts selecting the passage of a new annotation, from the block of
+    /// the current search match or else the first block on the screen.
+    fn annotate(&mut self, kind: AnnotationKind) {
+        let visible = self.visible_range();
+        let start = match &self.current_match {
+            Some(m)
+                if m.chapter == self.chapter
+                    && m.range.start >= visible.start
+                    && m.range.end <= visible.end =>
+            {
+                m.range.start
+            }
+            _ => visible.start,
+        };
+        let lines = &self.layout.lines[self.layout.line_at(start)..];
+        let Some(cursor) = lines.iter().find_map(|line| line.block) else {
+            return;
+        };
+        self.selection = Some(BlockSelection {
+            kind,
+            cursor,
+            anchor: None,
+        });
+    }
+
+    /// Handles a key while a passage is being selected.
+    fn selection_key(&mut self, key: Key) {
+        let Some(selection) = self.selection.as_mut() else {
+            return;
+        };
+        let count = self.ebook.chapters[self.chapter].blocks.len();
+        match key {
+            Key::Up if selection.cursor > 0 => selection.cursor -= 1,
+            Key::Down if selection.cursor + 1 < count => selection.cursor += 1,
+            Key::Char(' ') if selection.anchor.is_some() => selection.anchor = None,
+            Key::Char(' ') => selection.anchor = Some(selection.cursor),
+            Key::Char('\n') => {
+                let Some(selection) = self.selection.take() else {
+                    return;
+                };
+                self.ask_note(selection);
+                return;
+            }
+            Key::Esc => {
+                self.selection = None;
+                return;
+            }
+            _ => return,
+        }
+
+        // Scroll the block under the cursor into view.
+        let cursor = self.selection.as_ref().map_or(0, |s| s.cursor);
+        let block = &self.ebook.chapters[self.chapter].blocks[cursor];
+        let first = self.layout.line_at(block.range.start);
+        let last = self
+            .layout
+            .line_at(block.range.end.saturating_sub(1))
+            .max(first);
+        if first < self.scroll {
+            self.scroll_to(first);
+        } else if last >= self.scroll + self.height() {
+            let scroll = (last + 1 - self.height()).min(first);
+            self.scroll_to(scroll.min(self.max_scroll()));
+        }
+    }
+
+    // Asks for the note of an annotation of the selected blocks. A highlight
+    // of the block with the current search match covers just the match.
+    fn ask_note(&mut self, selection: BlockSelection) {
+        let blocks = &self.ebook.chapters[self.chapter].blocks;
+        let (first, last) = selection.blocks();
+        let mut range = blocks[first].range.start..blocks[last].range.end;
+        if let (Some(m), AnnotationKind::Highlight, None) =
+            (&self.current_match, selection.kind, selection.anchor)
+        {
+            let inside = m.range.start >= range.start && m.range.end <= range.end;
+            if m.chapter == self.chapter && inside {
+                range = m.range.clone();
+            }
+        }
+
+        self.prompt = Some(Prompt {
+            kind: PromptKind::Note(Annotation {
+                kind: selection.kind,
+                chapter: self.chapter,
+                range,
+                note: String::new(),
+            }),
+            input: String::new(),
+        });
+    }
+
+    fn add_annotation(&mut self, annotation: Annotation) {
+        self.message = Some(match annotation.kind {
+            AnnotationKind::Bookmark => "Bookmark added".to_string(),
+            AnnotationKind::Highlight => "Highlight added".to_string(),
+        });
+        let key = (annotation.chapter, annotation.range.start);
+        let index = self
+            .annotations
+            .partition_point(|a| (a.chapter, a.range.start) <= key);
+        self.annotations.insert(index, annotation);
+    }
+
+    // A line describing the annotation in the list.
+    fn annotation_title(&self, annotation: &Annotation) -> String {
+        let kind = match annotation.kind {
+            AnnotationKind::Bookmark => 'B',
+            AnnotationKind::Highlight => 'H',
+        };
+        let place = match self.toc_entry_at(annotation.chapter, annotation.range.start) {
+            Some(entry) => self.ebook.toc_entries()[entry].title.clone(),
+            None => format!("{}", annotation.chapter + 1),
+        };
+        let chapter = &self.ebook.chapters[annotation.chapter];
+        let excerpt = match chapter.text.get(annotation.range.clone()) {
+            Some(text) => text.split_whitespace().collect::<Vec<_>>().join(" "),
+            None => String::new(),
+        };
+        if annotation.note.is_empty() {
+            format!("{} {}: {}", kind, place, excerpt)
+        } else {
+            format!("{} {}: {} — {}", kind, place, annotation.note, excerpt)
+        }
+    }
+
+    /// Shows the list of annotations, with the ones of all chapters parsed.
+    fn open_annotation_list(&mut self) -> Result<()> {
+        let mut chapters: Vec<usize> = self.annotations.iter().map(|a| a.chapter).collect();
+        chapters.dedup();
+        for index in chapters {
+            self.read_chapter(index)?;
+        }
+        if self.annotations.is_empty() {
+            return Ok(());
+        }
+        let position = (self.chapter, self.offset());
+        let selected = self
+            .annotations
+            .partition_point(|a| (a.chapter, a.range.start) < position);
+        self.annotation_list = Some(selected.min(self.annotations.len() - 1));
+        Ok(())
+    }
+
+    /// Handles a key while the list of annotations is shown.
+    fn annotation_list_key(&mut self, key: Key, selected: usize) -> Result<()> {
+        let count = self.annotations.len();
+        match key {
+            Key::Up if selected > 0 => self.annotation_list = Some(selected - 1),
+            Key::Down if selected + 1 < count => self.annotation_list = Some(selected + 1),
+            Key::Char('\n') => {
+                self.annotation_list = None;
+                let annotation = &self.annotations[selected];
+                self.back.push((self.chapter, self.offset()));
+                self.open_chapter(annotation.chapter, annotation.range.start)?;
+            }
+            Key::Char('d') => {
+                self.annotations.remove(selected);
+                self.annotation_list = match count - 1 {
+                    0 => None,
+                    left => Some(selected.min(left - 1)),
+                };
+            }
+            Key::Char('m') | Key::Esc => self.annotation_list = None,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Opens the table of contents entry, landing on its fragment anchor.
+    fn jump_to_toc(&mut self, entry: usize) -> Result<()> {
+        let (index, offset) = self.ebook.toc_position(entry)?;
+        self.open_chapter(index, offset)
+    }
+
+    // The last toc entry at or before the offset into the chapter.
+    fn toc_entry_at(&self, chapter: usize, offset: usize) -> Option<usize> {
+        let chapters = &self.ebook.chapters;
+        self.ebook.toc_entries().iter().rposition(|entry| {
+            let start = match &entry.fragment {
+                Some(id) => chapters[entry.chapter].id_offset(id).unwrap_or(0),
+                None => 0,
+            };
+            (entry.chapter, start) <= (chapter, offset)
+        })
+    }
+
+    fn redraw(&self, screen: &mut Screen) -> Result<()> {
+        write!(screen, "{}{}", clear::All, cursor::Goto(1, 1))?;
+
+        let (term_width, term_height) = self.size;
+        let margin = term_width.saturating_sub(self.column_width()) / 2;
+        let last = self.layout.lines.len().min(self.scroll + self.height());
+        let chapter = &self.ebook.chapters[self.chapter];
+        let overlays = self.overlays();
+        for (y, i) in (self.scroll..last).enumerate() {
+            let line = render_line(chapter, &self.layout.lines[i], &overlays);
+            write!(screen, "{}{}", cursor::Goto(margin + 1, y as u16 + 1), line)?;
+        }
+        write!(screen, "{}", style::Reset)?;
+
+        let status = match (&self.prompt, &self.message) {
+            (Some(prompt), _) => match prompt.kind {
+                PromptKind::Search => format!("/{}", prompt.input),
+                PromptKind::Page => format!("Page: {}", prompt.input),
+                PromptKind::Note(_) => format!("Note: {}", prompt.input),
+            },
+            (None, Some(message)) => message.clone(),
+            (None, None) if self.selection.is_some() => {
+                "Select with Up and Down, Space to extend, Enter to add".to_string()
+            }
+            (None, None) => String::new(),
+        };
+        let status = truncate(&status, term_width as usize);
+        write!(screen, "{}{}", cursor::Goto(1, term_height), status)?;
+
+        if let Some(selected) = self.toc {
+            let titles: Vec<String> = self
+                .ebook
+                .toc_entries()
+                .iter()
+                .map(|entry| format!("{}{}", "  ".repeat(entry.depth), entry.title))
+                .collect();
+            let titles: Vec<&str> = titles.iter().map(String::as_str).collect();
+            self.draw_list(screen, "Contents", &titles, selected)?;
+        }
+        if let Some(selected) = self.annotation_list {
+            let titles: Vec<String> = self
+                .annotations
+                .iter()
+                .map(|a| self.annotation_title(a))
+                .collect();
+            let titles: Vec<&str> = titles.iter().map(String::as_str).collect();
+            self.draw_list(screen, "Bookmarks and highlights", &titles, selected)?;
+        }
+        if let Some(popup) = &self.note {
+            self.draw_note(screen, popup)?;
+        }
+
+        if self.prompt.is_some() {
+            write!(screen, "{}", cursor::Show)?;
+        } else {
+            write!(screen, "{}", cursor::Hide)?;
+        }
+        screen.flush()?;
+
+        Ok(())
+    }
+
+    // Draws a box listing `titles` with the selected one highlighted.
+    fn draw_list(
+        &self,
+        screen: &mut Screen,
+        name: &str,
+        titles: &[&str],
+        selected: usize,
+    ) -> Result<()> {
+        let (term_width, term_height) = self.size;
+        let width = term_width.saturating_sub(8).clamp(10, 60) as usize;
+        let height = (term_height.saturating_sub(4) as usize).min(titles.len().max(1));
+        let x = term_width.saturating_sub(width as u16 + 2) / 2 + 1;
+        let y = term_height.saturating_sub(height as u16 + 2) / 2 + 1;
+
+        // Keep the selected entry in view.
+        let first = selected.saturating_sub(height.saturating_sub(1));
+
+        draw_box(screen, (x, y), (width, height), name)?;
+        for row in 0..height {
+            let Some(title) = titles.get(first + row) else {
+                break;
+            };
+            let title = pad(&truncate(title, width), width);
+            write!(screen, "{}", cursor::Goto(x + 1, y + 1 + row as u16))?;
+            if first + row == selected {
+                write!(screen, "{}{}{}", style::Invert, title, style::Reset)?;
+            } else {
+                write!(screen, "{}", title)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn note_width(&self) -> usize {
+        self.column_width()
+            .min(self.size.0.saturating_sub(4))
+            .max(10) as usize
+    }
+
+    // The footnote of the popup and its lines at the popup width.
+    fn note_layout(&self, popup: &NotePopup) -> (&Chapter, Layout) {
+        let note = &self.ebook.chapters[popup.chapter].notes[popup.index];
+        (note, Layout::new(note, self.note_width()))
+    }
+
+    // Number of lines of the footnote popup.
+    fn note_height(&self, layout: &Layout) -> usize {
+        let max = self.size.1.saturating_sub(4).max(1) as usize;
+        layout.lines.len().clamp(1, max)
+    }
+
+    fn draw_note(&self, screen: &mut Screen, popup: &NotePopup) -> Result<()> {
+        let (term_width, term_height) = self.size;
+        let (note, layout) = self.note_layout(popup);
+        let width = self.note_width();
+        let height = self.note_height(&layout);
+        let x = term_width.saturating_sub(width as u16 + 2) / 2 + 1;
+        let y = term_height.saturating_sub(height as u16 + 2) / 2 + 1;
+
+        draw_box(screen, (x, y), (width, height), "Note")?;
+        for (row, line) in layout
+            .lines
+            .iter()
+            .skip(popup.scroll)
+            .take(height)
+            .enumerate()
+        {
+            let line = render_line(note, line, &[]);
+            write!(
+                screen,
+                "{}{}",
+                cursor::Goto(x + 1, y + 1 + row as u16),
+                line
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Handles a key while a footnote is shown.
+    fn note_key(&mut self, key: Key) {
+        let Some(popup) = &self.note else {
+            return;
+        };
+        let (_, layout) = self.note_layout(popup);
+        let max_scroll = layout.lines.len().saturating_sub(self.note_height(&layout));
+
+        let popup = self.note.as_mut().unwrap();
+        match key {
+            Key::Up if popup.scroll > 0 => popup.scroll -= 1,
+            Key::Down if popup.scroll < max_scroll => popup.scroll += 1,
+            Key::Char('\n') | Key::Char('q') | Key::Esc => self.note = None,
+            _ => {}
+        }
+    }
+
+    /// Handles a key while the table of contents is shown.
+    fn toc_key(&mut self, key: Key, selected: usize) -> Result<()> {
+        let count = self.ebook.toc_entries().len();
+        match key {
+            Key::Up if selected > 0 => self.toc = Some(selected - 1),
+            Key::Down if selected + 1 < count => self.toc = Some(selected + 1),
+            Key::Char('\n') => {
+                self.toc = None;
+                if selected < count {
+                    self.jump_to_toc(selected)?;
+                }
+            }
+            Key::Char('t') | Key::Esc => self.toc = None,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+// Draws a frame with `title` around a blank area at `inner` size.
+fn draw_box(screen: &mut Screen, at: (u16, u16), inner: (usize, usize), title: &str) -> Result<()> {
+    let (x, y) = at;
+    let (width, height) = inner;
+    let title = format!("─ {} ", title);
+
+    write!(screen, "{}┌{:─<width$}┐", cursor::Goto(x, y), title)?;
+    for row in 0..height {
+        let y = y + 1 + row as u16;
+        write!(screen, "{}│{:width$}│", cursor::Goto(x, y), "")?;
+    }
+    write!(
+        screen,
+        "{}└{:─<width$}┘",
+        cursor::Goto(x, y + 1 + height as u16),
+        ""
+    )?;
     Ok(())
 }
 
-pub fn read_ebook(ebook: &mut Epub) -> Result<()> {
+pub fn read_ebook<R: Read + Seek>(ebook: &mut Epub<R>, width: u16) -> Result<BookState> {
     // Wrap raw terminal with alternate screen
     let mut screen = stdout().into_raw_mode()?.into_alternate_screen()?;
     write!(screen, "{}{}", clear::All, cursor::Goto(1, 1)).unwrap();
-    let stdin = stdin();
-    let keys = stdin.keys();
+    let size = termion::terminal_size()?;
+    let events = spawn_events(size);
+
+    let num_chapters = ebook.chapters.len();
+    let mut state = BookState::load(ebook);
+    state.annotations.retain(|a| a.chapter < num_chapters);
+    // Skip the front matter of a book opened for the first time.
+    if !state.saved_position || state.chapter >= num_chapters {
+        (state.chapter, state.offset) = ebook.start_position()?;
+    }
 
-    let mut chp_num = 7;
-    let mut lines: Vec<&str> = ebook.read_chapter(chp_num)?.lines().collect();
-    let mut scroll = 0;
-    let mut last_size = termion::terminal_size()?;
+    let mut reader = Reader {
+        ebook,
+        chapter: 0,
+        layout: Layout::default(),
+        scroll: 0,
+        width,
+        size,
+        toc: None,
+        prompt: None,
+        search: None,
+        matches: Vec::new(),
+        current_match: None,
+        message: None,
+        focus: None,
+        back: Vec::new(),
+        note: None,
+        annotations: std::mem::take(&mut state.annotations),
+        annotation_list: None,
+        selection: None,
+    };
+    reader.open_chapter(state.chapter, state.offset)?;
 
     write!(screen, "{}", termion::cursor::Hide).unwrap();
 
-    log!("Number lines: {}", lines.len());
+    reader.redraw(&mut screen)?;
 
-    redraw(&mut screen, &lines, scroll, last_size)?;
+    for event in events.iter() {
+        let key = match event {
+            Event::Resize(size) => {
+                reader.resize(size);
+                reader.redraw(&mut screen)?;
+                continue;
+            }
+            Event::Key(key) => key,
+        };
 
-    for key in keys {
-        let current_size = termion::terminal_size()?;
-        if current_size != last_size {
-            last_size = current_size;
-            redraw(&mut screen, &lines, scroll, current_size)?;
+        reader.message = None;
+        if reader.note.is_some() {
+            reader.note_key(key);
+            reader.redraw(&mut screen)?;
+            continue;
+        }
+        if let Some(selected) = reader.toc {
+            reader.toc_key(key, selected)?;
+            reader.redraw(&mut screen)?;
+            continue;
+        }
+        if let Some(selected) = reader.annotation_list {
+            reader.annotation_list_key(key, selected)?;
+            reader.redraw(&mut screen)?;
+            continue;
+        }
+        if reader.selection.is_some() {
+            reader.selection_key(key);
+            reader.redraw(&mut screen)?;
+            continue;
+        }
+        if reader.prompt.is_some() {
+            reader.prompt_key(key)?;
+            reader.redraw(&mut screen)?;
             continue;
         }
 
-        match key? {
+        match key {
             Key::Char('q') => {
                 break;
             }
-            Key::Char('n') => {
-                chp_num += 1;
-                lines = ebook.read_chapter(chp_num)?.lines().collect();
-                scroll = 0;
+            Key::Char('t') if !reader.ebook.toc.is_empty() => {
+                let entry = reader.toc_entry_at(reader.chapter, reader.offset());
+                reader.toc = Some(entry.unwrap_or(0));
+            }
+            Key::Char('/') => {
+                reader.prompt = Some(Prompt {
+                    kind: PromptKind::Search,
+                    input: String::new(),
+                });
+            }
+            Key::Char('g') if reader.ebook.page_list.is_empty() => {
+                reader.message = Some("The book has no page numbers".to_string());
+            }
+            Key::Char('g') => {
+                reader.prompt = Some(Prompt {
+                    kind: PromptKind::Page,
+                    input: String::new(),
+                });
+            }
+            Key::Char('b') => {
+                reader.annotate(AnnotationKind::Bookmark);
             }
-            Key::Char('p') if chp_num > 0 => {
-                chp_num -= 1;
-                lines = ebook.read_chapter(chp_num)?.lines().collect();
-                scroll = 0;
+            Key::Char('h') => {
+                reader.annotate(AnnotationKind::Highlight);
             }
-            Key::Up if scroll > 0 => {
-                scroll -= 1;
-                log!("Scrolled up. New scroll position: {}", scroll);
+            Key::Char('m') if !reader.annotations.is_empty() => {
+                reader.open_annotation_list()?;
             }
-            Key::Down if scroll < lines.len().saturating_sub(current_size.1 as usize - 3) => {
-                scroll += 1;
-                log!("Scrolled down. New scroll position: {}", scroll);
+            Key::Char('n') if reader.search.is_some() => {
+                reader.find_match(true)?;
+            }
+            Key::Char('N') if reader.search.is_some() => {
+                reader.find_match(false)?;
+            }
+            Key::Esc if reader.search.is_some() => {
+                reader.clear_search();
+            }
+            Key::Char(c @ ('n' | 'p')) => {
+                // Right-to-left books turn their pages the other way round.
+                let forward = (c == 'n') != reader.ebook.right_to_left;
+                if let Some(index) = reader.ebook.adjacent_chapter(reader.chapter, forward) {
+                    reader.open_chapter(index, 0)?;
+                }
+            }
+            Key::Char('\t') => {
+                reader.focus_link(true);
+            }
+            Key::BackTab => {
+                reader.focus_link(false);
+            }
+            Key::Char('\n') if reader.focus.is_some() => {
+                reader.follow_link()?;
+            }
+            Key::Backspace if !reader.back.is_empty() => {
+                reader.go_back()?;
+            }
+            Key::Up if reader.scroll > 0 => {
+                reader.scroll_to(reader.scroll - 1);
+                log!("Scrolled up. New scroll position: {}", reader.scroll);
+            }
+            Key::Down if reader.scroll < reader.max_scroll() => {
+                reader.scroll_to(reader.scroll + 1);
+                log!("Scrolled down. New scroll position: {}", reader.scroll);
             }
             _ => {
                 continue;
             }
         }
-        redraw(&mut screen, &lines, scroll, current_size)?;
+        reader.redraw(&mut screen)?;
     }
 
+    state.annotations = std::mem::take(&mut reader.annotations);
+    state.chapter = reader.chapter;
+    state.offset = reader.offset();
+    let chapter_len = reader.text().len().max(1);
+    let read = reader.chapter as f64 + state.offset as f64 / chapter_len as f64;
+    state.progress = (read * 100.0 / num_chapters as f64).round() as u8;
+    state.save()?;
+
     write!(screen, "{}", cursor::Show).unwrap();
-    Ok(())
+    Ok(state)
 }
@@ -0,0 +1,143 @@
+//! The `render` module turns laid out lines into text for the terminal.
+
+use core::ops::Range;
+
+use termion::{color, style};
+
+use super::document::{BlockKind, Style};
+use super::epub::Chapter;
+use super::layout::{prefix, Line};
+
+/// How a marked range of the text is shown.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mark {
+    /// A passage highlighted by the reader.
+    Highlight,
+    /// A search match.
+    Match,
+    /// The search match that was jumped to.
+    CurrentMatch,
+    /// The link that follows on Enter.
+    Focus,
+    /// Blocks being selected for an annotation.
+    Selection,
+}
+
+/// A range of the chapter text drawn on top of the document styles.
+#[derive(Clone, Debug)]
+pub struct Overlay {
+    pub range: Range<usize>,
+    pub mark: Mark,
+}
+
+/// Renders the line with the escape codes for its styles. Later overlays are
+/// drawn over earlier ones.
+pub fn render_line(chapter: &Chapter, line: &Line, overlays: &[Overlay]) -> String {
+    let Some(block) = line.block.map(|i| &chapter.blocks[i]) else {
+        return String::new();
+    };
+
+    let mut result = String::from(prefix(block.kind, line.first));
+    let block_style = match block.kind {
+        BlockKind::Heading(_) => Style::BOLD,
+        BlockKind::Rule => return result + "* * *",
+        BlockKind::Image => return result + "[IMAGE]",
+        _ => Style::default(),
+    };
+
+    let overlays: Vec<&Overlay> = overlays
+        .iter()
+        .filter(|o| o.range.start < line.range.end && o.range.end > line.range.start)
+        .collect();
+
+    for span in &block.spans {
+        let start = span.range.start.max(line.range.start);
+        let end = span.range.end.min(line.range.end);
+        if start >= end {
+            continue;
+        }
+
+        // Split the span where overlays start or end.
+        let mut cuts: Vec<usize> = overlays
+            .iter()
+            .flat_map(|o| [o.range.start, o.range.end])
+            .filter(|&cut| start < cut && cut < end)
+            .collect();
+        cuts.push(end);
+        cuts.sort_unstable();
+
+        let mut from = start;
+        for to in cuts {
+            if from == to {
+                continue;
+            }
+            let mark = overlays
+                .iter()
+                .rev()
+                .find(|o| o.range.start <= from && from < o.range.end)
+                .map(|o| o.mark);
+            push_styled(
+                &mut result,
+                &chapter.text[from..to],
+                span.style.union(block_style),
+                mark,
+            );
+            from = to;
+        }
+    }
+
+    result
+}
+
+/// Renders the line as plain text, without any escape codes.
+pub fn plain_line(chapter: &Chapter, line: &Line) -> String {
+    let Some(block) = line.block.map(|i| &chapter.blocks[i]) else {
+        return String::new();
+    };
+
+    let result = String::from(prefix(block.kind, line.first));
+    match block.kind {
+        BlockKind::Rule => result + "* * *",
+        BlockKind::Image => result + "[IMAGE]",
+        _ => result + &chapter.text[line.range.clone()],
+    }
+}
+
+fn push_styled(result: &mut String, text: &str, style: Style, mark: Option<Mark>) {
+    if style == Style::default() && mark.is_none() {
+        result.push_str(text);
+        return;
+    }
+
+    if style.contains(Style::BOLD) {
+        result.push_str(style::Bold.as_ref());
+    }
+    if style.contains(Style::ITALIC) {
+        result.push_str(style::Italic.as_ref());
+    }
+    if style.contains(Style::UNDERLINE) {
+        result.push_str(style::Underline.as_ref());
+    }
+    match mark {
+        Some(Mark::Highlight) => {
+            result.push_str(&color::Bg(color::Blue).to_string());
+            result.push_str(&color::Fg(color::White).to_string());
+        }
+        Some(Mark::Match) => result.push_str(style::Invert.as_ref()),
+        Some(Mark::CurrentMatch) => {
+            result.push_str(&color::Bg(color::Yellow).to_string());
+            result.push_str(&color::Fg(color::Black).to_string());
+        }
+        Some(Mark::Focus) => {
+            result.push_str(&color::Bg(color::Cyan).to_string());
+            result.push_str(&color::Fg(color::Black).to_string());
+        }
+        Some(Mark::Selection) => {
+            result.push_str(&color::Bg(color::Green).to_string());
+            result.push_str(&color::Fg(color::Black).to_string());
+        }
+        None => {}
+    }
+    result.push_str(text);
+    result.push_str(style::Reset.as_ref());
+}
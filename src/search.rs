@@ -0,0 +1,106 @@
+//! The `search` module finds text across the chapters of a book.
+
+use core::ops::Range;
+use std::io::{Read, Seek};
+
+use regex::{Regex, RegexBuilder};
+
+use super::epub::Epub;
+use super::Result;
+
+/// A match of the search in a chapter.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Match {
+    pub chapter: usize,
+    pub range: Range<usize>,
+}
+
+#[derive(Debug)]
+pub struct Search {
+    pub query: String,
+    regex: Regex,
+}
+
+impl Search {
+    /// Creates a search for `query`, a regular expression which falls back to
+    /// plain text when it is not a valid one. The search ignores case unless
+    /// the query contains an uppercase letter.
+    pub fn new(query: &str) -> Self {
+        let ignore_case = !query.chars().any(char::is_uppercase);
+        let regex = RegexBuilder::new(query)
+            .case_insensitive(ignore_case)
+            .build()
+            .or_else(|_| {
+                RegexBuilder::new(&regex::escape(query))
+                    .case_insensitive(ignore_case)
+                    .build()
+            })
+            .expect("escaped query is a valid regex");
+
+        Search {
+            query: query.to_string(),
+            regex,
+        }
+    }
+
+    /// Returns all matches in the text.
+    pub fn find_all(&self, text: &str) -> Vec<Range<usize>> {
+        self.regex
+            .find_iter(text)
+            .filter(|m| !m.is_empty())
+            .map(|m| m.range())
+            .collect()
+    }
+
+    /// Finds the first match at or after `offset` in `chapter`, continuing through
+    /// the following chapters and wrapping around to the start of the book.
+    pub fn next<R: Read + Seek>(
+        &self,
+        ebook: &mut Epub<R>,
+        chapter: usize,
+        offset: usize,
+    ) -> Result<Option<Match>> {
+        let count = ebook.chapters.len();
+        for i in 0..=count {
+            let index = (chapter + i) % count;
+            let text = ebook.read_chapter(index)?;
+            let found = self
+                .find_all(text)
+                .into_iter()
+                .find(|range| i > 0 || range.start >= offset);
+            if let Some(range) = found {
+                return Ok(Some(Match {
+                    chapter: index,
+                    range,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Finds the last match before `offset` in `chapter`, continuing through
+    /// the preceding chapters and wrapping around to the end of the book.
+    pub fn prev<R: Read + Seek>(
+        &self,
+        ebook: &mut Epub<R>,
+        chapter: usize,
+        offset: usize,
+    ) -> Result<Option<Match>> {
+        let count = ebook.chapters.len();
+        for i in 0..=count {
+            let index = (chapter + count * 2 - i) % count;
+            let text = ebook.read_chapter(index)?;
+            let found = self
+                .find_all(text)
+                .into_iter()
+                .rfind(|range| i > 0 || range.start < offset);
+            if let Some(range) = found {
+                return Ok(Some(Match {
+                    chapter: index,
+                    range,
+                }));
+            }
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,381 @@
+//! The `store` module persists per-book reading state between sessions.
+
+use core::ops::Range;
+use std::{
+    env,
+    ffi::OsString,
+    fs,
+    io::{Read, Seek, Write},
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::epub::Epub;
+use super::layout::{pad, truncate};
+use super::Result;
+
+/// Reading state of a single book, stored under the data directory.
+#[derive(Debug, Default)]
+pub struct BookState {
+    key: String,
+    /// Index of the chapter in the spine.
+    pub chapter: usize,
+    /// Byte offset into `Chapter.text` of the first visible line.
+    pub offset: usize,
+    /// Whether `chapter` and `offset` were read from a saved state.
+    pub saved_position: bool,
+    /// How much of the book has been read, in percent.
+    pub progress: u8,
+    /// Bookmarks and highlights, in reading order.
+    pub annotations: Vec<Annotation>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AnnotationKind {
+    Bookmark,
+    Highlight,
+}
+
+/// A bookmark or highlight of a passage, with an optional note.
+#[derive(Clone, Debug)]
+pub struct Annotation {
+    pub kind: AnnotationKind,
+    /// Index of the chapter in the spine.
+    pub chapter: usize,
+    /// Byte range of the passage in `Chapter.text`.
+    pub range: Range<usize>,
+    pub note: String,
+}
+
+/// A book that was opened before.
+#[derive(Debug)]
+pub struct HistoryEntry {
+    pub path: PathBuf,
+    pub title: Option<String>,
+    pub creator: Option<String>,
+    /// Seconds since the unix epoch.
+    pub last_opened: u64,
+    pub progress: u8,
+}
+
+/// Recently opened books, most recent first.
+#[derive(Debug, Default)]
+pub struct History {
+    pub entries: Vec<HistoryEntry>,
+}
+
+/// Returns `$XDG_DATA_HOME/rpub`, falling back to `~/.local/share/rpub`.
+pub fn data_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local/share"),
+    };
+    Some(base.join("rpub"))
+}
+
+/// Returns a stable key for the book, derived from its unique identifier if
+/// it has one, otherwise from its location on disk or else its title.
+pub fn book_key<R: Read + Seek>(ebook: &Epub<R>) -> String {
+    let metadata = ebook.metadata.as_ref();
+    let source = match (metadata.and_then(|m| m.identifier()), &ebook.file_path) {
+        (Some(identifier), _) => identifier.to_string(),
+        (None, Some(path)) => path.display().to_string(),
+        (None, None) => metadata
+            .and_then(|m| m.title())
+            .unwrap_or_default()
+            .to_string(),
+    };
+    format!("{:016x}", fnv1a(source.as_bytes()))
+}
+
+// 64-bit FNV-1a, unlike `DefaultHasher` it is stable across releases.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl BookState {
+    /// Loads the state of the book, or a fresh state if none was saved.
+    pub fn load<R: Read + Seek>(ebook: &Epub<R>) -> Self {
+        let mut state = BookState {
+            key: book_key(ebook),
+            ..Default::default()
+        };
+
+        let Some(text) = state.path().and_then(|p| fs::read_to_string(p).ok()) else {
+            return state;
+        };
+
+        for line in text.lines() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            match fields[..] {
+                ["position", chapter, offset] => {
+                    state.chapter = chapter.parse().unwrap_or(0);
+                    state.offset = offset.parse().unwrap_or(0);
+                    state.saved_position = true;
+                }
+                ["progress", progress] => state.progress = progress.parse().unwrap_or(0),
+                [kind @ ("bookmark" | "highlight"), chapter, start, end, note] => {
+                    let (Ok(chapter), Ok(start), Ok(end)) =
+                        (chapter.parse(), start.parse(), end.parse())
+                    else {
+                        continue;
+                    };
+                    state.annotations.push(Annotation {
+                        kind: match kind {
+                            "bookmark" => AnnotationKind::Bookmark,
+                            _ => AnnotationKind::Highlight,
+                        },
+                        chapter,
+                        range: start..end,
+                        note: unescape(note),
+                    });
+                }
+                _ => {}
+            }
+        }
+        state
+    }
+
+    /// Writes the state back to the data directory.
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = self.path() else {
+            return Ok(());
+        };
+        create_parent(&path)?;
+
+        let mut file = fs::File::create(path)?;
+        writeln!(file, "position\t{}\t{}", self.chapter, self.offset)?;
+        writeln!(file, "progress\t{}", self.progress)?;
+        for annotation in &self.annotations {
+            let kind = match annotation.kind {
+                AnnotationKind::Bookmark => "bookmark",
+                AnnotationKind::Highlight => "highlight",
+            };
+            writeln!(
+                file,
+                "{}\t{}\t{}\t{}\t{}",
+                kind,
+                annotation.chapter,
+                annotation.range.start,
+                annotation.range.end,
+                escape(&annotation.note)
+            )?;
+        }
+        Ok(())
+    }
+
+    fn path(&self) -> Option<PathBuf> {
+        Some(data_dir()?.join("books").join(&self.key))
+    }
+}
+
+impl History {
+    pub fn load() -> Self {
+        let mut history = History::default();
+        let Some(text) = Self::path().and_then(|p| fs::read_to_string(p).ok()) else {
+            return history;
+        };
+
+        for line in text.lines() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            if let [last_opened, progress, path, title, creator] = fields[..] {
+                history.entries.push(HistoryEntry {
+                    path: unescape_path(path),
+                    title: Some(unescape(title)).filter(|t| !t.is_empty()),
+                    creator: Some(unescape(creator)).filter(|c| !c.is_empty()),
+                    last_opened: last_opened.parse().unwrap_or(0),
+                    progress: progress.parse().unwrap_or(0),
+                });
+            }
+        }
+        history
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = Self::path() else {
+            return Ok(());
+        };
+        create_parent(&path)?;
+
+        let mut file = fs::File::create(path)?;
+        for entry in &self.entries {
+            writeln!(
+                file,
+                "{}\t{}\t{}\t{}\t{}",
+                entry.last_opened,
+                entry.progress,
+                escape_path(&entry.path),
+                escape(entry.title.as_deref().unwrap_or_default()),
+                escape(entry.creator.as_deref().unwrap_or_default()),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Moves the book to the top of the history, updating its progress.
+    /// Books which weren't opened from a file are left out.
+    pub fn record<R: Read + Seek>(&mut self, ebook: &Epub<R>, progress: u8) {
+        let Some(path) = &ebook.file_path else {
+            return;
+        };
+        self.entries.retain(|e| &e.path != path);
+
+        let metadata = ebook.metadata.as_ref();
+        self.entries.insert(
+            0,
+            HistoryEntry {
+                path: path.clone(),
+                title: metadata.and_then(|m| m.title()).map(String::from),
+                creator: metadata.and_then(|m| m.creator()).map(String::from),
+                last_opened: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs()),
+                progress,
+            },
+        );
+    }
+
+    /// Returns the entry `n`, counting from 1 like the printed table.
+    pub fn get(&self, n: usize) -> Option<&HistoryEntry> {
+        self.entries.get(n.checked_sub(1)?)
+    }
+
+    pub fn print(&self) {
+        if self.entries.is_empty() {
+            println!("No books in history");
+            return;
+        }
+
+        println!(
+            "{:>3}  {:>4}  {:<16}  {:<32}  {:<24}  Path",
+            "#", "Read", "Last opened", "Title", "Author"
+        );
+        for (i, entry) in self.entries.iter().enumerate() {
+            let title = entry.title.clone().unwrap_or_else(|| {
+                entry
+                    .path
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string()
+            });
+            println!(
+                "{:>3}  {:>3}%  {:<16}  {}  {}  {}",
+                i + 1,
+                entry.progress,
+                format_time(entry.last_opened),
+                pad(&truncate(&title, 32), 32),
+                pad(&truncate(entry.creator.as_deref().unwrap_or("-"), 24), 24),
+                entry.path.display(),
+            );
+        }
+    }
+
+    fn path() -> Option<PathBuf> {
+        Some(data_dir()?.join("history"))
+    }
+}
+
+// Fields are tab separated, so tabs and newlines in titles have to be escaped.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    String::from_utf8_lossy(&unescape_bytes(text)).into_owned()
+}
+
+// Paths are escaped like titles, and the bytes which aren't UTF-8 are
+// written as `\xHH` so that they read back the same.
+fn escape_path(path: &Path) -> String {
+    let mut result = String::new();
+    for chunk in path.as_os_str().as_bytes().utf8_chunks() {
+        result.push_str(&escape(chunk.valid()));
+        for b in chunk.invalid() {
+            result.push_str(&format!("\\x{:02x}", b));
+        }
+    }
+    result
+}
+
+fn unescape_path(text: &str) -> PathBuf {
+    PathBuf::from(OsString::from_vec(unescape_bytes(text)))
+}
+
+fn unescape_bytes(text: &str) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = || {
+            let digits = text.get(i + 2..i + 4)?;
+            u8::from_str_radix(digits, 16)
+                .ok()
+                .filter(|_| !digits.starts_with('+'))
+        };
+        let (byte, len) = match (bytes[i], bytes.get(i + 1)) {
+            (b'\\', Some(b't')) => (b'\t', 2),
+            (b'\\', Some(b'n')) => (b'\n', 2),
+            (b'\\', Some(b'\\')) => (b'\\', 2),
+            (b'\\', Some(b'x')) => hex().map_or((b'\\', 1), |byte| (byte, 4)),
+            (byte, _) => (byte, 1),
+        };
+        result.push(byte);
+        i += len;
+    }
+    result
+}
+
+// Formats a unix timestamp as `YYYY-MM-DD HH:MM` in UTC.
+fn format_time(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let (hour, minute) = (secs % 86400 / 3600, secs % 3600 / 60);
+
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year, month, day, hour, minute
+    )
+}
+
+fn create_parent(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_round_trips() {
+        let path = PathBuf::from(OsString::from_vec(b"/b\\x41/a\tb\nc\\\xff.epub".to_vec()));
+        let escaped = escape_path(&path);
+        assert!(!escaped.contains(['\t', '\n']));
+        assert_eq!(escaped, "/b\\\\x41/a\\tb\\nc\\\\\\xff.epub");
+        assert_eq!(unescape_path(&escaped), path);
+    }
+
+    #[test]
+    fn title_round_trips() {
+        let title = "A\ttitle\\x41\non two lines";
+        assert_eq!(unescape(&escape(title)), title);
+    }
+}
@@ -0,0 +1,502 @@
+//! The `toc` module reads the table of contents, landmarks and page list from
+//! an EPUB3 navigation document or an EPUB2 NCX file.
+
+use super::epub::{has_epub_type, NS_OPS};
+use super::href;
+use super::xml::{Document, Node};
+
+/// An entry of the table of contents.
+#[derive(Debug)]
+pub struct TocEntry {
+    pub title: String,
+    /// Index of the chapter in the spine.
+    pub chapter: usize,
+    /// Id of the element in the chapter the entry points to.
+    pub fragment: Option<String>,
+    /// Nesting level, 0 for top level entries.
+    pub depth: usize,
+    pub children: Vec<TocEntry>,
+}
+
+impl TocEntry {
+    /// Returns the entries of the tree in reading order, parents before
+    /// their children.
+    pub fn flatten(entries: &[TocEntry]) -> Vec<&TocEntry> {
+        let mut flat = Vec::new();
+        for entry in entries {
+            flat.push(entry);
+            flat.extend(TocEntry::flatten(&entry.children));
+        }
+        flat
+    }
+}
+
+/// A major part of the book, like its cover or the start of its body matter.
+#[derive(Debug)]
+pub struct Landmark {
+    /// The `epub:type` of the landmark, like `cover`, `toc` or `bodymatter`.
+    pub kind: String,
+    pub title: String,
+    pub chapter: usize,
+    pub fragment: Option<String>,
+}
+
+/// Where a page of the print edition starts.
+#[derive(Debug)]
+pub struct PageTarget {
+    /// The page number as printed, which may also be roman like `xii`.
+    pub label: String,
+    pub chapter: usize,
+    pub fragment: Option<String>,
+}
+
+/// Maps a path in the container to the index of its chapter in the spine.
+pub type ChapterIndex<'a> = dyn Fn(&str) -> Option<usize> + 'a;
+
+/// Reads the toc `<nav>` of the EPUB3 navigation document at `path`.
+pub fn parse_nav(doc: &Document, path: &str, chapter: &ChapterIndex) -> Vec<TocEntry> {
+    let navs: Vec<Node> = doc
+        .descendants()
+        .filter(|n| n.has_tag_name("nav"))
+        .collect();
+    let nav = navs
+        .iter()
+        .find(|n| has_epub_type(**n, &["toc"]))
+        .or(navs.first());
+    let Some(ol) = nav.and_then(|n| nav_ol(*n)) else {
+        return Vec::new();
+    };
+
+    let mut entries = nav_list(ol, path, chapter);
+    set_depth(&mut entries, 0);
+    entries
+}
+
+// Reads the `<li>` items of an `<ol>` in a navigation document.
+fn nav_list(ol: Node, path: &str, chapter: &ChapterIndex) -> Vec<TocEntry> {
+    let mut entries = Vec::new();
+    for li in ol.children().filter(|n| n.has_tag_name("li")) {
+        let label = li
+            .children()
+            .find(|n| n.has_tag_name("a") || n.has_tag_name("span"));
+        let title = label.map(text_content).unwrap_or_default();
+        let target = label
+            .and_then(|n| n.attribute("href"))
+            .and_then(|target| resolve_target(path, target, chapter));
+        let children = match li.children().find(|n| n.has_tag_name("ol")) {
+            Some(ol) => nav_list(ol, path, chapter),
+            None => Vec::new(),
+        };
+        push_entry(&mut entries, title, target, children);
+    }
+    entries
+}
+
+/// Reads the landmarks `<nav>` of the EPUB3 navigation document at `path`.
+pub fn parse_landmarks(doc: &Document, path: &str, chapter: &ChapterIndex) -> Vec<Landmark> {
+    let links = nav_links(doc, "landmarks");
+    links
+        .filter_map(|a| {
+            let (chapter, fragment) = resolve_target(path, a.attribute("href")?, chapter)?;
+            let kind = a.attribute((NS_OPS, "type"))?;
+            Some(Landmark {
+                kind: kind.to_string(),
+                title: text_content(a),
+                chapter,
+                fragment,
+            })
+        })
+        .collect()
+}
+
+/// Reads the page-list `<nav>` of the EPUB3 navigation document at `path`.
+pub fn parse_page_list(doc: &Document, path: &str, chapter: &ChapterIndex) -> Vec<PageTarget> {
+    let links = nav_links(doc, "page-list");
+    links
+        .filter_map(|a| {
+            let (chapter, fragment) = resolve_target(path, a.attribute("href")?, chapter)?;
+            Some(PageTarget {
+                label: text_content(a),
+                chapter,
+                fragment,
+            })
+        })
+        .collect()
+}
+
+// The links in the `<nav>` of the given `epub:type`.
+fn nav_links<'a, 'input>(
+    doc: &'a Document<'input>,
+    kind: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    doc.descendants()
+        .filter(move |n| n.has_tag_name("nav") && has_epub_type(*n, &[kind]))
+        .filter_map(nav_ol)
+        .flat_map(|ol| ol.descendants().filter(|n| n.has_tag_name("a")))
+}
+
+fn nav_ol<'a, 'input>(nav: Node<'a, 'input>) -> Option<Node<'a, 'input>> {
+    nav.children().find(|n| n.has_tag_name("ol"))
+}
+
+/// Reads the `<pageList>` of the NCX file at `path`.
+pub fn parse_ncx_page_list(doc: &Document, path: &str, chapter: &ChapterIndex) -> Vec<PageTarget> {
+    let Some(page_list) = doc.descendants().find(|n| n.has_tag_name("pageList")) else {
+        return Vec::new();
+    };
+
+    let mut targets: Vec<Node> = page_list
+        .children()
+        .filter(|n| n.has_tag_name("pageTarget"))
+        .collect();
+    targets.sort_by_key(|n| n.attribute("playOrder").and_then(|o| o.parse::<u32>().ok()));
+    targets
+        .into_iter()
+        .filter_map(|target| {
+            let src = target
+                .children()
+                .find(|n| n.has_tag_name("content"))
+                .and_then(|n| n.attribute("src"))?;
+            let (chapter, fragment) = resolve_target(path, src, chapter)?;
+            let label = target
+                .descendants()
+                .find(|n| n.has_tag_name("text"))
+                .map(text_content)
+                .or_else(|| target.attribute("value").map(String::from))?;
+            Some(PageTarget {
+                label,
+                chapter,
+                fragment,
+            })
+        })
+        .collect()
+}
+
+/// Reads the EPUB2 `<guide>` of the package document at `path` as landmarks,
+/// using the EPUB3 names for its reference types.
+pub fn parse_guide(guide: Node, path: &str, chapter: &ChapterIndex) -> Vec<Landmark> {
+    guide
+        .children()
+        .filter(|n| n.is_element() && n.tag_name().name() == "reference")
+        .filter_map(|reference| {
+            let target = reference.attribute("href")?;
+            let (chapter, fragment) = resolve_target(path, target, chapter)?;
+            let kind = match reference.attribute("type")? {
+                "text" => "bodymatter",
+                kind => kind,
+            };
+            Some(Landmark {
+                kind: kind.to_string(),
+                title: reference.attribute("title").unwrap_or(kind).to_string(),
+                chapter,
+                fragment,
+            })
+        })
+        .collect()
+}
+
+/// Reads the `<navMap>` of the NCX file at `path`.
+pub fn parse_ncx(doc: &Document, path: &str, chapter: &ChapterIndex) -> Vec<TocEntry> {
+    let Some(nav_map) = doc.descendants().find(|n| n.has_tag_name("navMap")) else {
+        return Vec::new();
+    };
+
+    let mut entries = nav_points(nav_map, path, chapter);
+    set_depth(&mut entries, 0);
+    entries
+}
+
+// Reads the `<navPoint>` children of a node, ordered by their `playOrder`.
+fn nav_points(parent: Node, path: &str, chapter: &ChapterIndex) -> Vec<TocEntry> {
+    let mut points: Vec<Node> = parent
+        .children()
+        .filter(|n| n.has_tag_name("navPoint"))
+        .collect();
+    points.sort_by_key(|n| n.attribute("playOrder").and_then(|o| o.parse::<u32>().ok()));
+
+    let mut entries = Vec::new();
+    for point in points {
+        let title = point
+            .children()
+            .find(|n| n.has_tag_name("navLabel"))
+            .and_then(|n| n.children().find(|n| n.has_tag_name("text")))
+            .map(text_content)
+            .unwrap_or_default();
+        let target = point
+            .children()
+            .find(|n| n.has_tag_name("content"))
+            .and_then(|n| n.attribute("src"))
+            .and_then(|target| resolve_target(path, target, chapter));
+        let children = nav_points(point, path, chapter);
+        push_entry(&mut entries, title, target, children);
+    }
+    entries
+}
+
+// Adds an entry to `entries`. An entry without a target in the spine takes
+// the target of its first child, or is replaced by its children without one.
+fn push_entry(
+    entries: &mut Vec<TocEntry>,
+    title: String,
+    target: Option<(usize, Option<String>)>,
+    children: Vec<TocEntry>,
+) {
+    let target = target.or_else(|| {
+        let first = children.first()?;
+        Some((first.chapter, first.fragment.clone()))
+    });
+    match target {
+        Some((chapter, fragment)) if !title.is_empty() => entries.push(TocEntry {
+            title,
+            chapter,
+            fragment,
+            depth: 0,
+            children,
+        }),
+        _ => entries.extend(children),
+    }
+}
+
+fn set_depth(entries: &mut [TocEntry], depth: usize) {
+    for entry in entries {
+        entry.depth = depth;
+        set_depth(&mut entry.children, depth + 1);
+    }
+}
+
+// Resolves an href in the file at `path` to a chapter index and fragment.
+fn resolve_target(
+    path: &str,
+    target: &str,
+    chapter: &ChapterIndex,
+) -> Option<(usize, Option<String>)> {
+    let index = chapter(&href::resolve(path, target)?)?;
+    let fragment = href::split_fragment(target).1.map(href::percent_decode);
+    Some((index, fragment))
+}
+
+// All text of the node and its descendants with whitespace collapsed.
+fn text_content(node: Node) -> String {
+    let text: Vec<&str> = node
+        .descendants()
+        .filter(Node::is_text)
+        .filter_map(|n| n.text())
+        .flat_map(str::split_whitespace)
+        .collect();
+    text.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPINE: [&str; 3] = ["OEBPS/c1.xhtml", "OEBPS/c2.xhtml", "OEBPS/c3.xhtml"];
+
+    fn chapter(path: &str) -> Option<usize> {
+        SPINE.iter().position(|p| *p == path)
+    }
+
+    // The title, chapter, fragment and depth of each entry in reading order.
+    fn outline(entries: &[TocEntry]) -> Vec<(&str, usize, Option<&str>, usize)> {
+        TocEntry::flatten(entries)
+            .into_iter()
+            .map(|e| (e.title.as_str(), e.chapter, e.fragment.as_deref(), e.depth))
+            .collect()
+    }
+
+    #[test]
+    fn ncx_tree() {
+        let ncx = r#"<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/">
+<navMap>
+  <navPoint playOrder="4">
+    <navLabel><text>Part  Two</text></navLabel>
+    <content src="c3.xhtml"/>
+  </navPoint>
+  <navPoint playOrder="1">
+    <navLabel><text>Part One</text></navLabel>
+    <content src="c1.xhtml"/>
+    <navPoint playOrder="2">
+      <navLabel><text>Section 1</text></navLabel>
+      <content src="c2.xhtml#s1"/>
+      <navPoint playOrder="3">
+        <navLabel><text>Section 1.1</text></navLabel>
+        <content src="c2.xhtml#s1.1"/>
+      </navPoint>
+    </navPoint>
+  </navPoint>
+</navMap>
+</ncx>"#;
+        let doc = Document::parse(ncx).unwrap();
+        let entries = parse_ncx(&doc, "OEBPS/toc.ncx", &chapter);
+        assert_eq!(
+            outline(&entries),
+            [
+                ("Part One", 0, None, 0),
+                ("Section 1", 1, Some("s1"), 1),
+                ("Section 1.1", 1, Some("s1.1"), 2),
+                ("Part Two", 2, None, 0),
+            ]
+        );
+        assert_eq!(entries[0].children.len(), 1);
+    }
+
+    #[test]
+    fn nav_tree() {
+        let nav = r#"<html xmlns="http://www.w3.org/1999/xhtml"
+    xmlns:epub="http://www.idpf.org/2007/ops">
+<body>
+  <nav epub:type="landmarks"><ol><li><a href="c1.xhtml">Start</a></li></ol></nav>
+  <nav epub:type="toc">
+    <ol>
+      <li><a href="c1.xhtml">One</a></li>
+      <li>
+        <span>Two</span>
+        <ol>
+          <li><a href="c2.xhtml#a">Two <em>A</em></a></li>
+          <li><a href="c2.xhtml#b">Two B</a></li>
+          <li><a href="c2.xhtml#c%20d">Two C</a></li>
+        </ol>
+      </li>
+      <li><a href="missing.xhtml">Missing</a></li>
+      <li>
+        <a href="elsewhere.xhtml"></a>
+        <ol><li><a href="c3.xhtml">Three</a></li></ol>
+      </li>
+    </ol>
+  </nav>
+</body>
+</html>"#;
+        let doc = Document::parse(nav).unwrap();
+        let entries = parse_nav(&doc, "OEBPS/nav.xhtml", &chapter);
+        assert_eq!(
+            outline(&entries),
+            [
+                ("One", 0, None, 0),
+                // A heading without a link points where its first child does.
+                ("Two", 1, Some("a"), 0),
+                ("Two A", 1, Some("a"), 1),
+                ("Two B", 1, Some("b"), 1),
+                ("Two C", 1, Some("c d"), 1),
+                // An untitled entry is replaced by its children.
+                ("Three", 2, None, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn nav_landmarks_and_page_list() {
+        let nav = r#"<html xmlns="http://www.w3.org/1999/xhtml"
+    xmlns:epub="http://www.idpf.org/2007/ops">
+<body>
+  <nav epub:type="toc"><ol><li><a href="c1.xhtml">One</a></li></ol></nav>
+  <nav epub:type="landmarks">
+    <ol>
+      <li><a epub:type="cover" href="c1.xhtml">Cover</a></li>
+      <li><a epub:type="bodymatter" href="c2.xhtml#start">Start of Content</a></li>
+      <li><a href="c3.xhtml">No type</a></li>
+    </ol>
+  </nav>
+  <nav epub:type="page-list" hidden="">
+    <ol>
+      <li><a href="c2.xhtml#p1">1</a></li>
+      <li><a href="c3.xhtml#p2">2</a></li>
+      <li><a href="missing.xhtml#p3">3</a></li>
+    </ol>
+  </nav>
+</body>
+</html>"#;
+        let doc = Document::parse(nav).unwrap();
+        let landmarks = parse_landmarks(&doc, "OEBPS/nav.xhtml", &chapter);
+        let landmarks: Vec<_> = landmarks
+            .iter()
+            .map(|l| {
+                (
+                    l.kind.as_str(),
+                    l.title.as_str(),
+                    l.chapter,
+                    l.fragment.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            landmarks,
+            [
+                ("cover", "Cover", 0, None),
+                ("bodymatter", "Start of Content", 1, Some("start")),
+            ]
+        );
+
+        let pages = parse_page_list(&doc, "OEBPS/nav.xhtml", &chapter);
+        let pages: Vec<_> = pages
+            .iter()
+            .map(|p| (p.label.as_str(), p.chapter, p.fragment.as_deref()))
+            .collect();
+        assert_eq!(pages, [("1", 1, Some("p1")), ("2", 2, Some("p2"))]);
+    }
+
+    #[test]
+    fn ncx_page_list() {
+        let ncx = r#"<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/">
+<navMap/>
+<pageList>
+  <pageTarget playOrder="2" value="2">
+    <navLabel><text>ii</text></navLabel>
+    <content src="c2.xhtml#p2"/>
+  </pageTarget>
+  <pageTarget playOrder="1" value="1">
+    <content src="c1.xhtml#p1"/>
+  </pageTarget>
+</pageList>
+</ncx>"#;
+        let doc = Document::parse(ncx).unwrap();
+        let pages = parse_ncx_page_list(&doc, "OEBPS/toc.ncx", &chapter);
+        let pages: Vec<_> = pages
+            .iter()
+            .map(|p| (p.label.as_str(), p.chapter, p.fragment.as_deref()))
+            .collect();
+        // A target without a label is named by its value.
+        assert_eq!(pages, [("1", 0, Some("p1")), ("ii", 1, Some("p2"))]);
+    }
+
+    #[test]
+    fn guide_landmarks() {
+        let opf = r#"<package xmlns="http://www.idpf.org/2007/opf">
+<guide>
+  <reference type="cover" href="Text/c1.xhtml" title="Cover"/>
+  <reference type="text" href="Text/c2.xhtml#start"/>
+  <reference type="toc" href="Text/missing.xhtml"/>
+</guide>
+</package>"#;
+        let doc = Document::parse(opf).unwrap();
+        let guide = doc.root_element().first_element_child().unwrap();
+        let chapter = |p: &str| chapter(&p.replace("Text/", ""));
+        let landmarks = parse_guide(guide, "OEBPS/content.opf", &chapter);
+        let landmarks: Vec<_> = landmarks
+            .iter()
+            .map(|l| {
+                (
+                    l.kind.as_str(),
+                    l.title.as_str(),
+                    l.chapter,
+                    l.fragment.as_deref(),
+                )
+            })
+            .collect();
+        // The EPUB2 `text` type is the body matter.
+        assert_eq!(
+            landmarks,
+            [
+                ("cover", "Cover", 0, None),
+                ("bodymatter", "bodymatter", 1, Some("start")),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_toc() {
+        let doc = Document::parse("<ncx><docTitle/></ncx>").unwrap();
+        assert!(parse_ncx(&doc, "OEBPS/toc.ncx", &chapter).is_empty());
+        let doc = Document::parse("<html><body><nav/></body></html>").unwrap();
+        assert!(parse_nav(&doc, "OEBPS/nav.xhtml", &chapter).is_empty());
+    }
+}
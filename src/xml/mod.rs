@@ -29,7 +29,11 @@ use core::ops::Range;
 
 use alloc::vec::Vec;
 
+mod entities;
 mod parse;
+// Only tested until something reads a book as a stream of events.
+#[cfg(test)]
+mod pull;
 mod tokenizer;
 
 
@@ -98,9 +102,18 @@ pub struct Document<'input> {
     nodes: Vec<NodeData<'input>>,
     attributes: Vec<AttributeData<'input>>,
     namespaces: Namespaces<'input>,
+    warnings: Vec<Error>,
 }
 
 impl<'input> Document<'input> {
+    /// Returns the errors which were recovered from while parsing.
+    ///
+    /// Always empty unless [`ParsingOptions::recover`] is set.
+    #[inline]
+    pub fn warnings(&self) -> &[Error] {
+        &self.warnings
+    }
+
     /// Returns the root node.
     ///
     /// # Examples
@@ -315,8 +328,8 @@ struct ShortRange {
 impl From<Range<usize>> for ShortRange {
     #[inline]
     fn from(range: Range<usize>) -> Self {
-        debug_assert!(range.start <= core::u32::MAX as usize);
-        debug_assert!(range.end <= core::u32::MAX as usize);
+        debug_assert!(range.start <= u32::MAX as usize);
+        debug_assert!(range.end <= u32::MAX as usize);
         ShortRange::new(range.start as u32, range.end as u32)
     }
 }
@@ -348,7 +361,7 @@ impl NodeId {
     /// Construct a new `NodeId` from a `u32`.
     #[inline]
     pub fn new(id: u32) -> Self {
-        debug_assert!(id < core::u32::MAX);
+        debug_assert!(id < u32::MAX);
 
         // We are using `NonZeroU32` to reduce overhead of `Option<NodeId>`.
         NodeId(NonZeroU32::new(id + 1).unwrap())
@@ -378,7 +391,7 @@ impl From<usize> for NodeId {
     #[inline]
     fn from(id: usize) -> Self {
         // We already checked that `id` is limited by u32::MAX.
-        debug_assert!(id <= core::u32::MAX as usize);
+        debug_assert!(id <= u32::MAX as usize);
         NodeId::new(id as u32)
     }
 }
@@ -608,7 +621,7 @@ impl<'input> Namespaces<'input> {
         }) {
             Ok(sorted_idx) => self.sorted_order[sorted_idx],
             Err(sorted_idx) => {
-                if self.values.len() > core::u16::MAX as usize {
+                if self.values.len() > u16::MAX as usize {
                     return Err(Error::NamespacesLimitReached);
                 }
                 let idx = NamespaceIdx(self.values.len() as u16);
@@ -912,7 +925,7 @@ impl<'a, 'input: 'a> Node<'a, 'input> {
         self.attributes()
             .find(|a| a.data.name.as_expanded_name(self.doc) == name)
             .map(|a| a.value())
-            .ok_or_else(|| Error::NodeNotFound(""))
+            .ok_or(Error::NodeNotFound(""))
     }
 
     /// Returns element's attribute value.
@@ -1103,7 +1116,7 @@ impl<'a, 'input: 'a> Node<'a, 'input> {
 
     /// Returns the last element child of this node.
     pub fn last_element_child(&self) -> Option<Self> {
-        self.children().filter(|n| n.is_element()).last()
+        self.children().rfind(|n| n.is_element())
     }
 
     /// Returns true if this node has children.
@@ -4,14 +4,21 @@ use core::ops::Range;
 
 use super::{
     AttributeData, Document, ExpandedNameIndexed, NamespaceIdx, Namespaces, NodeData, NodeId,
-    NodeKind, ShortRange, StringStorage, TextPos, NS_XMLNS_URI, NS_XML_PREFIX, NS_XML_URI,
-    XMLNS,
+    NodeKind, ShortRange, StringStorage, TextPos, NS_XMLNS_URI, NS_XML_PREFIX, NS_XML_URI, XMLNS,
 };
 
-use super::tokenizer::{self, Reference, StrSpan, Stream};
+use super::entities::html_entity;
+use super::tokenizer::{self, Reference, StrSpan, Stream, XmlCharExt};
 
 type Result<T> = core::result::Result<T, Error>;
 
+// How deeply entities may reference each other.
+const ENTITY_DEPTH_LIMIT: u8 = 10;
+
+// How many bytes of entity values may be expanded in a document. A billion
+// laughs document takes exponentially more with each level of entities.
+const ENTITY_EXPANSION_LIMIT: usize = 1 << 20;
+
 /// A list of all possible errors.
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub enum Error {
@@ -55,6 +62,12 @@ pub enum Error {
     /// A reference to an entity that was not defined in the DTD.
     UnknownEntityReference(String, TextPos),
 
+    /// An entity references itself, or entities are nested too deeply.
+    EntityReferenceLoop(TextPos),
+
+    /// The entities of the DTD expanded to too much text.
+    EntityExpansionLimitReached(TextPos),
+
     /// A malformed entity reference.
     ///
     /// A `&` character inside an attribute value or text indicates an entity reference.
@@ -141,6 +154,8 @@ impl Error {
             Error::UnexpectedCloseTag(_, _, pos) => pos,
             Error::UnexpectedEntityCloseTag(pos) => pos,
             Error::UnknownEntityReference(_, pos) => pos,
+            Error::EntityReferenceLoop(pos) => pos,
+            Error::EntityExpansionLimitReached(pos) => pos,
             Error::MalformedEntityReference(pos) => pos,
             Error::InvalidAttributeValue(pos) => pos,
             Error::DuplicatedAttribute(_, pos) => pos,
@@ -211,6 +226,12 @@ impl core::fmt::Display for Error {
             Error::UnknownEntityReference(ref name, pos) => {
                 write!(f, "unknown entity reference '{}' at {}", name, pos)
             }
+            Error::EntityReferenceLoop(pos) => {
+                write!(f, "a possible entity reference loop is detected at {}", pos)
+            }
+            Error::EntityExpansionLimitReached(pos) => {
+                write!(f, "entities expand to too much text at {}", pos)
+            }
             Error::InvalidAttributeValue(pos) => {
                 write!(f, "unescaped '<' found at {}", pos)
             }
@@ -277,6 +298,103 @@ impl std::error::Error for Error {
     }
 }
 
+// A general entity declared in the DTD.
+struct Entity<'input> {
+    name: &'input str,
+    value: StrSpan<'input>,
+}
+
+// Resolves the references in text and attribute values, and collects the
+// errors recovered from. Shared by the tree builder and the event reader.
+pub(crate) struct Resolver<'input> {
+    text: &'input str,
+    opt: ParsingOptions,
+    entities: Vec<Entity<'input>>,
+    // Number of entity values being expanded.
+    entity_depth: u8,
+    // Bytes of entity values expanded so far.
+    entity_expansion: usize,
+    warnings: Vec<Error>,
+}
+
+impl<'input> Resolver<'input> {
+    pub(crate) fn new(text: &'input str, opt: ParsingOptions) -> Self {
+        Resolver {
+            text,
+            opt,
+            entities: Vec::new(),
+            entity_depth: 0,
+            entity_expansion: 0,
+            warnings: Vec::new(),
+        }
+    }
+
+    pub(crate) fn warnings(&self) -> &[Error] {
+        &self.warnings
+    }
+
+    // Collects the error when recovering, otherwise returns it.
+    pub(crate) fn warn(&mut self, error: Error) -> Result<()> {
+        if !self.opt.recover {
+            return Err(error);
+        }
+        self.warnings.push(error);
+        Ok(())
+    }
+
+    pub(crate) fn declare(&mut self, name: &'input str, value: StrSpan<'input>) {
+        // The first declaration of an entity is the binding one.
+        if self.entity(name).is_none() {
+            self.entities.push(Entity { name, value });
+        }
+    }
+
+    fn entity(&self, name: &str) -> Option<StrSpan<'input>> {
+        self.entities
+            .iter()
+            .find(|e| e.name == name)
+            .map(|e| e.value)
+    }
+
+    // Starts expanding the value of an entity referenced at `pos`, failing on
+    // a loop or on too much text.
+    fn enter_entity(&mut self, value: StrSpan, pos: usize) -> Result<()> {
+        if self.entity_depth >= ENTITY_DEPTH_LIMIT {
+            return Err(Error::EntityReferenceLoop(self.err_pos_at(pos)));
+        }
+        self.entity_expansion += value.as_str().len();
+        if self.entity_expansion > ENTITY_EXPANSION_LIMIT {
+            return Err(Error::EntityExpansionLimitReached(self.err_pos_at(pos)));
+        }
+        self.entity_depth += 1;
+        Ok(())
+    }
+
+    fn leave_entity(&mut self) {
+        self.entity_depth -= 1;
+    }
+
+    // Returns the text of a named character reference of HTML, if they are resolved.
+    fn html_entity_text(&self, name: &str) -> Option<&'static str> {
+        self.opt.html_entities.then(|| html_entity(name)).flatten()
+    }
+
+    fn err_pos_at(&self, pos: usize) -> TextPos {
+        Stream::new(self.text).gen_text_pos_from(pos)
+    }
+}
+
+// A consumer of the text resolved by `process_text`.
+pub(crate) trait TextSink<'input> {
+    fn resolver(&mut self) -> &mut Resolver<'input>;
+
+    // Takes a run of text, which may follow the previous one.
+    fn append_text(&mut self, text: StringStorage<'input>) -> Result<()>;
+
+    // Parses the value of an entity holding elements, referenced at `pos`.
+    fn parse_entity_markup(&mut self, value: StrSpan<'input>, pos: usize) -> Result<()>;
+}
+
 struct TempAttributeData<'input> {
     prefix: &'input str,
     local: &'input str,
@@ -288,6 +406,24 @@ struct TempAttributeData<'input> {
     eq_len: u8,
 }
 
+/// Options of [`Document::parse_with_options`].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+pub struct ParsingOptions {
+    /// Resolves the named character references of HTML5, like `&nbsp;`, which
+    /// XHTML documents use without declaring them.
+    ///
+    /// Default: false
+    pub html_entities: bool,
+
+    /// Recovers from malformed markup instead of failing, as far as possible.
+    /// Mismatched close tags close the elements left open or are skipped, a
+    /// bare `&` is kept as text and invalid characters are skipped. The errors
+    /// are collected in [`Document::warnings`].
+    ///
+    /// Default: false
+    pub recover: bool,
+}
+
 impl<'input> Document<'input> {
     /// Parses the input XML string.
     ///
@@ -301,7 +437,12 @@ impl<'input> Document<'input> {
     /// assert_eq!(doc.descendants().count(), 2); // root node + `e` element node
     /// ```
     #[inline]
-    pub fn parse(text: &str) -> Result<Document> {
+    pub fn parse(text: &str) -> Result<Document<'_>> {
+        Self::parse_with_options(text, ParsingOptions::default())
+    }
+
+    /// Parses the input XML string using the specified parsing options.
+    pub fn parse_with_options(text: &str, opt: ParsingOptions) -> Result<Document<'_>> {
         // Trying to guess rough nodes and attributes amount.
         let nodes_capacity = text.bytes().filter(|c| *c == b'<').count();
         let attributes_capacity = text.bytes().filter(|c| *c == b'=').count();
@@ -312,6 +453,7 @@ impl<'input> Document<'input> {
             nodes: Vec::with_capacity(nodes_capacity),
             attributes: Vec::with_capacity(attributes_capacity),
             namespaces: Namespaces::default(),
+            warnings: Vec::new(),
         };
 
         // Add a root node.
@@ -334,21 +476,25 @@ impl<'input> Document<'input> {
             after_text: false,
             parent_id: NodeId::new(0),
             tag_name: TagNameSpan::new_null(),
+            entity_pos: None,
+            entity_floor: 0,
+            resolver: Resolver::new(text, opt),
             doc,
         };
         ctx.parent_prefixes.push("");
 
         tokenizer::parse(text, &mut ctx)?;
 
+        if ctx.parent_prefixes.len() > 1 {
+            ctx.warn(Error::UnclosedRootNode)?;
+        }
+
         let mut doc = ctx.doc;
+        doc.warnings = ctx.resolver.warnings;
         if !doc.root().children().any(|n| n.is_element()) {
             return Err(Error::NoRootNode);
         }
 
-        if ctx.parent_prefixes.len() > 1 {
-            return Err(Error::UnclosedRootNode);
-        }
-
         doc.nodes.shrink_to_fit();
         doc.attributes.shrink_to_fit();
         doc.namespaces.shrink_to_fit();
@@ -385,6 +531,11 @@ struct Context<'input> {
     after_text: bool,
     parent_id: NodeId,
     tag_name: TagNameSpan<'input>,
+    // Where the entity being expanded is referenced.
+    entity_pos: Option<usize>,
+    // Number of open elements which the expanded entity can't close.
+    entity_floor: usize,
+    resolver: Resolver<'input>,
     doc: Document<'input>,
 }
 
@@ -421,6 +572,33 @@ impl<'input> Context<'input> {
     fn err_pos_at(&self, pos: usize) -> TextPos {
         self.doc.text_pos_at(pos)
     }
+
+    fn warn(&mut self, error: Error) -> Result<()> {
+        self.resolver.warn(error)
+    }
+
+    // Ends the innermost open element.
+    fn close_element(&mut self) {
+        self.awaiting_subtree.push(self.parent_id);
+        if let Some(id) = self.doc.nodes[self.parent_id.get_usize()].parent {
+            self.parent_id = id;
+            self.parent_prefixes.pop();
+            debug_assert!(!self.parent_prefixes.is_empty());
+        } else {
+            unreachable!("should be already checked by the tokenizer");
+        }
+    }
+
+    // Returns the qualified name of the innermost open element, if there is one.
+    fn parent_qname(&self) -> Option<String> {
+        match self.doc.nodes[self.parent_id.get_usize()].kind {
+            NodeKind::Element { ref tag_name, .. } => {
+                let prefix = self.parent_prefixes.last().unwrap();
+                Some(gen_qname_string(prefix, tag_name.local_name))
+            }
+            _ => None,
+        }
+    }
 }
 
 impl<'input> tokenizer::XmlEvents<'input> for Context<'input> {
@@ -450,10 +628,44 @@ impl<'input> tokenizer::XmlEvents<'input> for Context<'input> {
             tokenizer::Token::Text(text, range) => {
                 process_text(text, range, self)?;
             }
+            // The tree keeps neither CDATA nor comments.
+            tokenizer::Token::Cdata(..) | tokenizer::Token::Comment(..) => {}
+            tokenizer::Token::EntityDeclaration(name, value) => {
+                self.resolver.declare(name, value);
+            }
         }
 
         Ok(())
     }
+
+    fn recover(&mut self, error: Error) -> Result<()> {
+        self.warn(error)
+    }
+}
+
+impl<'input> TextSink<'input> for Context<'input> {
+    fn resolver(&mut self) -> &mut Resolver<'input> {
+        &mut self.resolver
+    }
+
+    fn append_text(&mut self, text: StringStorage<'input>) -> Result<()> {
+        append_text(text, self)?;
+        self.after_text = true;
+        Ok(())
+    }
+
+    fn parse_entity_markup(&mut self, value: StrSpan<'input>, pos: usize) -> Result<()> {
+        let mut value_stream = Stream::from_substr(self.doc.text, value.range());
+        let tag_name = core::mem::replace(&mut self.tag_name, TagNameSpan::new_null());
+        // Entities can't close the elements open where they are referenced.
+        let outer = (self.entity_pos, self.entity_floor);
+        self.entity_pos = Some(outer.0.unwrap_or(pos));
+        self.entity_floor = self.parent_prefixes.len() - 1;
+        tokenizer::parse_content(&mut value_stream, self)?;
+        self.tag_name = tag_name;
+        (self.entity_pos, self.entity_floor) = outer;
+        Ok(())
+    }
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -466,7 +678,7 @@ fn process_attribute<'input>(
     value: StrSpan<'input>,
     ctx: &mut Context<'input>,
 ) -> Result<()> {
-    let value = normalize_attribute(value, ctx)?;
+    let value = normalize_attribute(value, &mut ctx.resolver)?;
 
     if prefix == XMLNS {
         // The xmlns namespace MUST NOT be declared as the default namespace.
@@ -546,9 +758,8 @@ fn process_element<'input>(
         // <root>&p;</root>
 
         if let tokenizer::ElementEnd::Close(..) = end_token {
-            return Err(Error::UnexpectedEntityCloseTag(
-                ctx.err_pos_at(token_range.start),
-            ));
+            let pos = ctx.err_pos_at(token_range.start);
+            return ctx.warn(Error::UnexpectedEntityCloseTag(pos));
         } else {
             unreachable!("should be already checked by the tokenizer");
         }
@@ -580,29 +791,42 @@ fn process_element<'input>(
             ctx.awaiting_subtree.push(new_element_id);
         }
         tokenizer::ElementEnd::Close(prefix, local) => {
-            let parent_node = &mut ctx.doc.nodes[ctx.parent_id.get_usize()];
-            // should never panic as we start with the single prefix of the
-            // root node and always push another one when changing the parent
-            let parent_prefix = *ctx.parent_prefixes.last().unwrap();
-
-            if let NodeKind::Element { ref tag_name, .. } = parent_node.kind {
-                if prefix != parent_prefix || local != tag_name.local_name {
-                    return Err(Error::UnexpectedCloseTag(
-                        gen_qname_string(parent_prefix, tag_name.local_name),
-                        gen_qname_string(prefix, local),
-                        ctx.err_pos_at(token_range.start),
-                    ));
+            // Number of open elements inside the closed one, which only a
+            // malformed document leaves open.
+            let mut depth = None;
+            let mut id = ctx.parent_id;
+            let closable = ctx.parent_prefixes.len() - 1 - ctx.entity_floor;
+            let parent_prefixes = ctx.parent_prefixes.iter().rev().take(closable);
+            for (i, parent_prefix) in parent_prefixes.enumerate() {
+                let node = &ctx.doc.nodes[id.get_usize()];
+                let NodeKind::Element { ref tag_name, .. } = node.kind else {
+                    break;
+                };
+                if *parent_prefix == prefix && tag_name.local_name == local {
+                    depth = Some(i);
+                    break;
                 }
+                id = node.parent.unwrap();
             }
-            ctx.awaiting_subtree.push(ctx.parent_id);
 
-            if let Some(id) = parent_node.parent {
-                ctx.parent_id = id;
-                ctx.parent_prefixes.pop();
-                debug_assert!(!ctx.parent_prefixes.is_empty());
-            } else {
-                unreachable!("should be already checked by the tokenizer");
+            let pos = ctx.err_pos_at(token_range.start);
+            let qname = gen_qname_string(prefix, local);
+            let Some(depth) = depth else {
+                // No open element has the name, the tag is left out.
+                return ctx.warn(match (ctx.entity_pos, ctx.parent_qname()) {
+                    (Some(entity_pos), _) => {
+                        Error::UnexpectedEntityCloseTag(ctx.err_pos_at(entity_pos))
+                    }
+                    (None, Some(expected)) => Error::UnexpectedCloseTag(expected, qname, pos),
+                    (None, None) => Error::UnknownToken(pos),
+                });
+            };
+            for _ in 0..depth {
+                let expected = ctx.parent_qname().unwrap();
+                ctx.warn(Error::UnexpectedCloseTag(expected, qname.clone(), pos))?;
+                ctx.close_element();
             }
+            ctx.close_element();
         }
         tokenizer::ElementEnd::Open => {
             let tag_ns_idx = get_ns_idx_by_prefix(
@@ -660,7 +884,7 @@ fn resolve_attributes(namespaces: ShortRange, ctx: &mut Context) -> Result<Short
         return Ok(ShortRange::new(0, 0));
     }
 
-    if ctx.doc.attributes.len() + ctx.current_attributes.len() >= core::u32::MAX as usize {
+    if ctx.doc.attributes.len() + ctx.current_attributes.len() >= u32::MAX as usize {
         return Err(Error::AttributesLimitReached);
     }
 
@@ -703,23 +927,36 @@ fn resolve_attributes(namespaces: ShortRange, ctx: &mut Context) -> Result<Short
     Ok((start_idx..ctx.doc.attributes.len()).into())
 }
 
-fn process_text<'input>(
+pub(crate) fn process_text<'input, S: TextSink<'input>>(
     text: &'input str,
     range: Range<usize>,
-    ctx: &mut Context<'input>,
+    sink: &mut S,
 ) -> Result<()> {
     // Add text as is if it has only valid characters.
     if !text.bytes().any(|b| b == b'&' || b == b'\r') {
-        append_text(StringStorage::Borrowed(text), ctx)?;
-        ctx.after_text = true;
-        return Ok(());
+        return sink.append_text(StringStorage::Borrowed(text));
     }
 
     let mut text_buffer = TextBuffer::new();
+    expand_text(range, &mut text_buffer, sink)?;
+
+    if !text_buffer.is_empty() {
+        sink.append_text(StringStorage::new_owned(text_buffer.finish()))?;
+    }
+
+    Ok(())
+}
+
+// Pushes the text in `range` to the buffer, resolving its references.
+fn expand_text<'input, S: TextSink<'input>>(
+    range: Range<usize>,
+    text_buffer: &mut TextBuffer,
+    sink: &mut S,
+) -> Result<()> {
     let mut is_as_is = false; // TODO: explain
-    let mut stream = Stream::from_substr(ctx.doc.text, range.clone());
+    let mut stream = Stream::from_substr(sink.resolver().text, range);
     while !stream.at_end() {
-        match parse_next_chunk(&mut stream)? {
+        match parse_next_chunk(&mut stream, sink.resolver())? {
             NextChunk::Byte(c) => {
                 if is_as_is {
                     text_buffer.push_raw(c);
@@ -736,14 +973,29 @@ fn process_text<'input>(
                     is_as_is = true;
                 }
             }
+            NextChunk::Text(text) => {
+                text.bytes().for_each(|b| text_buffer.push_raw(b));
+                is_as_is = true;
+            }
+            NextChunk::Entity(value, pos) => {
+                is_as_is = false;
+                sink.resolver().enter_entity(value, pos)?;
+                if value.as_str().contains('<') {
+                    // The value holds elements, so the text before it becomes a
+                    // node of its own.
+                    if !text_buffer.is_empty() {
+                        let text = core::mem::replace(text_buffer, TextBuffer::new());
+                        sink.append_text(StringStorage::new_owned(text.finish()))?;
+                    }
+                    sink.parse_entity_markup(value, pos)?;
+                } else {
+                    expand_text(value.range(), text_buffer, sink)?;
+                }
+                sink.resolver().leave_entity();
+            }
         }
     }
 
-    if !text_buffer.is_empty() {
-        append_text(StringStorage::new_owned(text_buffer.finish()), ctx)?;
-        ctx.after_text = true;
-    }
-
     Ok(())
 }
 
@@ -771,12 +1023,20 @@ fn append_text<'input>(
     Ok(())
 }
 
-enum NextChunk {
+enum NextChunk<'input> {
     Byte(u8),
     Char(char),
+    /// The text of a named entity, or the reference itself when recovering
+    /// from an unknown one.
+    Text(&'input str),
+    /// The value of an entity declared in the DTD, and where it is referenced.
+    Entity(StrSpan<'input>, usize),
 }
 
-fn parse_next_chunk<'a>(stream: &mut Stream<'a>) -> Result<NextChunk> {
+fn parse_next_chunk<'input>(
+    stream: &mut Stream,
+    resolver: &mut Resolver<'input>,
+) -> Result<NextChunk<'input>> {
     debug_assert!(!stream.at_end());
 
     // Safe, because we already checked that stream is not at the end.
@@ -788,9 +1048,22 @@ fn parse_next_chunk<'a>(stream: &mut Stream<'a>) -> Result<NextChunk> {
         let start = stream.pos();
         match stream.try_consume_reference() {
             Some(Reference::Char(ch)) => Ok(NextChunk::Char(ch)),
+            Some(Reference::Entity(name)) => match resolver.entity(name) {
+                Some(value) => Ok(NextChunk::Entity(value, start)),
+                None => match resolver.html_entity_text(name) {
+                    Some(text) => Ok(NextChunk::Text(text)),
+                    None => {
+                        let pos = stream.gen_text_pos_from(start);
+                        resolver.warn(Error::UnknownEntityReference(name.to_string(), pos))?;
+                        Ok(NextChunk::Text(&resolver.text[start..stream.pos()]))
+                    }
+                },
+            },
             None => {
-                let pos = stream.gen_text_pos_from(start);
-                Err(Error::MalformedEntityReference(pos))
+                // Keep a bare `&` as text.
+                resolver.warn(Error::MalformedEntityReference(stream.gen_text_pos()))?;
+                stream.advance(1);
+                Ok(NextChunk::Byte(c))
             }
         }
     } else {
@@ -800,13 +1073,15 @@ fn parse_next_chunk<'a>(stream: &mut Stream<'a>) -> Result<NextChunk> {
 }
 
 // https://www.w3.org/TR/REC-xml/#AVNormalize
-fn normalize_attribute<'input>(
+pub(crate) fn normalize_attribute<'input>(
     text: StrSpan<'input>,
-    ctx: &mut Context<'input>,
+    resolver: &mut Resolver<'input>,
 ) -> Result<StringStorage<'input>> {
-    if is_normalization_required(&text) {
+    // Only a recovering tokenizer leaves invalid characters in the value.
+    let has_invalid_chars = resolver.opt.recover && text.as_str().chars().any(|c| !c.is_xml_char());
+    if has_invalid_chars || is_normalization_required(&text) {
         let mut text_buffer = TextBuffer::new();
-        _normalize_attribute(text, &mut text_buffer, ctx)?;
+        _normalize_attribute(text, &mut text_buffer, resolver)?;
         Ok(StringStorage::new_owned(text_buffer.finish()))
     } else {
         Ok(StringStorage::Borrowed(text.as_str()))
@@ -825,12 +1100,30 @@ fn is_normalization_required(text: &StrSpan) -> bool {
     text.as_str().bytes().any(check)
 }
 
-fn _normalize_attribute(text: StrSpan, buffer: &mut TextBuffer, ctx: &mut Context) -> Result<()> {
-    let mut stream = Stream::from_substr(ctx.doc.text, text.range());
+fn _normalize_attribute(
+    text: StrSpan,
+    buffer: &mut TextBuffer,
+    resolver: &mut Resolver,
+) -> Result<()> {
+    let mut stream = Stream::from_substr(resolver.text, text.range());
     while !stream.at_end() {
         // Safe, because we already checked that the stream is not at the end.
         let c = stream.curr_byte_unchecked();
 
+        // Invalid characters left in the value by a recovering tokenizer.
+        let bytes = &resolver.text.as_bytes()[stream.pos()..];
+        let invalid_len = match c {
+            b'\t' | b'\n' | b'\r' => 0,
+            0..=0x1F => 1,
+            // U+FFFE and U+FFFF.
+            0xEF if matches!(bytes, [_, 0xBF, 0xBE | 0xBF, ..]) => 3,
+            _ => 0,
+        };
+        if invalid_len > 0 {
+            stream.advance(invalid_len);
+            continue;
+        }
+
         if c != b'&' {
             stream.advance(1);
             buffer.push_from_attr(c, stream.curr_byte().ok());
@@ -847,9 +1140,33 @@ fn _normalize_attribute(text: StrSpan, buffer: &mut TextBuffer, ctx: &mut Contex
                     buffer.push_raw(b);
                 }
             }
+            Some(Reference::Entity(name)) => match resolver.entity(name) {
+                Some(value) => {
+                    // Elements can't go into an attribute value.
+                    if value.as_str().contains('<') {
+                        return Err(Error::InvalidAttributeValue(resolver.err_pos_at(start)));
+                    }
+                    resolver.enter_entity(value, start)?;
+                    _normalize_attribute(value, buffer, resolver)?;
+                    resolver.leave_entity();
+                }
+                None => {
+                    let text = match resolver.html_entity_text(name) {
+                        Some(text) => text,
+                        None => {
+                            let pos = stream.gen_text_pos_from(start);
+                            resolver.warn(Error::UnknownEntityReference(name.to_string(), pos))?;
+                            &resolver.text[start..stream.pos()]
+                        }
+                    };
+                    text.bytes().for_each(|b| buffer.push_raw(b));
+                }
+            },
             None => {
-                let pos = stream.gen_text_pos_from(start);
-                return Err(Error::MalformedEntityReference(pos));
+                // Keep a bare `&` as text.
+                resolver.warn(Error::MalformedEntityReference(stream.gen_text_pos()))?;
+                stream.advance(1);
+                buffer.push_raw(c);
             }
         }
     }
@@ -900,7 +1217,7 @@ fn get_ns_idx_by_prefix(
     }
 }
 
-fn gen_qname_string(prefix: &str, local: &str) -> String {
+pub(crate) fn gen_qname_string(prefix: &str, local: &str) -> String {
     if prefix.is_empty() {
         local.to_string()
     } else {
@@ -1007,3 +1324,101 @@ impl TextBuffer {
         String::from_utf8(self.buffer).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECOVER: ParsingOptions = ParsingOptions {
+        html_entities: false,
+        recover: true,
+    };
+
+    #[test]
+    fn entity_cannot_close_outer_element() {
+        let xml = "<!DOCTYPE r [<!ENTITY e '<b>x</b></p>'>]><r><p>&e;y</p></r>";
+        let err = Document::parse(xml).unwrap_err();
+        assert!(matches!(err, Error::UnexpectedEntityCloseTag(_)), "{}", err);
+
+        let doc = Document::parse_with_options(xml, RECOVER).unwrap();
+        assert!(matches!(
+            doc.warnings(),
+            [Error::UnexpectedEntityCloseTag(_)]
+        ));
+        let p = doc.root_element().first_child().unwrap();
+        let children: Vec<_> = p
+            .children()
+            .map(|n| (n.tag_name().name(), n.text()))
+            .collect();
+        assert_eq!(children, [("b", Some("x")), ("", Some("y"))]);
+    }
+
+    #[test]
+    fn entity_closes_its_own_elements() {
+        let xml = "<!DOCTYPE r [<!ENTITY e '<b><i>x</b>'>]><r>&e;</r>";
+        let doc = Document::parse_with_options(xml, RECOVER).unwrap();
+        assert!(matches!(doc.warnings(), [Error::UnexpectedCloseTag(..)]));
+        let b = doc.root_element().first_child().unwrap();
+        assert_eq!(b.tag_name().name(), "b");
+        assert_eq!(b.first_child().unwrap().tag_name().name(), "i");
+    }
+
+    #[test]
+    fn invalid_characters_in_attributes() {
+        let xml = "<r a='x\u{1}y\u{FFFF}' b='&#x9;\u{1}&#x9;'/>";
+        let err = Document::parse(xml).unwrap_err();
+        assert!(matches!(err, Error::NonXmlChar('\u{1}', _)), "{}", err);
+
+        let doc = Document::parse_with_options(xml, RECOVER).unwrap();
+        assert_eq!(doc.warnings().len(), 3);
+        assert_eq!(doc.root_element().attribute("a"), Some("xy"));
+        assert_eq!(doc.root_element().attribute("b"), Some("\t\t"));
+    }
+
+    #[test]
+    fn entity_depth_limit() {
+        // Each entity references the next one, the deepest holds text.
+        let nested = |depth: usize| {
+            let mut dtd = String::new();
+            for i in 0..depth {
+                dtd += &format!("<!ENTITY e{} '&e{};'>", i, i + 1);
+            }
+            dtd += &format!("<!ENTITY e{} 'text'>", depth);
+            format!("<!DOCTYPE r [{}]><r>&e0;</r>", dtd)
+        };
+
+        let xml = nested(usize::from(ENTITY_DEPTH_LIMIT) - 1);
+        let doc = Document::parse(&xml).unwrap();
+        assert_eq!(doc.root_element().text(), Some("text"));
+
+        let xml = nested(usize::from(ENTITY_DEPTH_LIMIT));
+        let err = Document::parse(&xml).unwrap_err();
+        assert!(matches!(err, Error::EntityReferenceLoop(_)), "{}", err);
+
+        let xml = "<!DOCTYPE r [<!ENTITY a '&b;'><!ENTITY b '&a;'>]><r>&a;</r>";
+        let err = Document::parse(xml).unwrap_err();
+        assert!(matches!(err, Error::EntityReferenceLoop(_)), "{}", err);
+    }
+
+    #[test]
+    fn entity_expansion_limit() {
+        // A billion laughs: each entity holds ten references to the last.
+        let mut dtd = "<!ENTITY l0 'lol'>".to_string();
+        for i in 1..10 {
+            let value = format!("&l{};", i - 1).repeat(10);
+            dtd += &format!("<!ENTITY l{} '{}'>", i, value);
+        }
+        let xml = format!("<!DOCTYPE r [{}]><r>&l9;</r>", dtd);
+        let err = Document::parse(&xml).unwrap_err();
+        assert!(
+            matches!(err, Error::EntityExpansionLimitReached(_)),
+            "{}",
+            err
+        );
+
+        // Below the limit, the same entities expand fully.
+        let xml = format!("<!DOCTYPE r [{}]><r>&l3;</r>", dtd);
+        let doc = Document::parse(&xml).unwrap();
+        assert_eq!(doc.root_element().text(), Some("lol".repeat(1000).as_str()));
+    }
+}
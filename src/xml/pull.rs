@@ -0,0 +1,712 @@
+//! A pull parser, which reads a document as a stream of events instead of
+//! building a tree. It runs the same tokenizer as [`Document::parse`], one
+//! tag or run of text at a time, and only keeps the elements open at the
+//! current event.
+//!
+//! [`Document::parse`]: super::Document::parse
+
+use std::collections::VecDeque;
+
+use super::parse::{gen_qname_string, normalize_attribute, process_text, Resolver, TextSink};
+use super::tokenizer::{self, ElementEnd, StrSpan, Stream, Token};
+use super::{
+    Error, ParsingOptions, StringStorage, TextPos, NS_XMLNS_URI, NS_XML_PREFIX, NS_XML_URI, XMLNS,
+};
+
+type Result<T> = core::result::Result<T, Error>;
+
+/// An element or attribute name, with its namespace resolved.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Name<'input> {
+    /// The namespace URI, if the name has one.
+    pub namespace: Option<StringStorage<'input>>,
+    /// The prefix as written, empty if there is none.
+    pub prefix: &'input str,
+    /// The local name.
+    pub local: &'input str,
+}
+
+impl Name<'_> {
+    /// Checks the name against a namespace URI and a local name.
+    pub fn is(&self, namespace: &str, local: &str) -> bool {
+        self.local == local && self.namespace.as_deref() == Some(namespace)
+    }
+}
+
+/// An attribute of a start tag.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct EventAttribute<'input> {
+    /// The name of the attribute, without a namespace unless it is prefixed.
+    pub name: Name<'input>,
+    /// The value with its references resolved.
+    pub value: StringStorage<'input>,
+}
+
+/// An event of an [`EventReader`]. Each one has the position where its markup
+/// starts, or where the entity it was expanded from is referenced.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Event<'input> {
+    /// The start tag of an element. Namespace declarations aren't among the
+    /// attributes. An empty element tag is followed by its `EndElement`.
+    StartElement {
+        name: Name<'input>,
+        attributes: Vec<EventAttribute<'input>>,
+        pos: TextPos,
+    },
+    /// The end of an element.
+    EndElement { name: Name<'input>, pos: TextPos },
+    /// Text with its references resolved. Text broken up by an invalid
+    /// character or an entity comes in more than one event.
+    Text {
+        text: StringStorage<'input>,
+        pos: TextPos,
+    },
+    /// The content of a CDATA section, as is.
+    Cdata { text: &'input str, pos: TextPos },
+    /// The content of a comment outside of the DTD.
+    Comment { text: &'input str, pos: TextPos },
+}
+
+impl Event<'_> {
+    /// Returns the position of the event.
+    pub fn pos(&self) -> TextPos {
+        match *self {
+            Event::StartElement { pos, .. } => pos,
+            Event::EndElement { pos, .. } => pos,
+            Event::Text { pos, .. } => pos,
+            Event::Cdata { pos, .. } => pos,
+            Event::Comment { pos, .. } => pos,
+        }
+    }
+}
+
+/// Reads a document as an iterator of [`Event`]s.
+///
+/// Errors end the iteration. When recovering, the errors which could be
+/// skipped over are collected in [`EventReader::warnings`] instead, and the
+/// elements left open at the end of the document get their end events.
+///
+/// # Examples
+///
+/// ```
+/// use roxmltree::*;
+///
+/// let mut words = 0;
+/// for event in EventReader::new("<p>Two <b>words</b></p>") {
+///     if let Event::Text { text, .. } = event.unwrap() {
+///         words += text.split_whitespace().count();
+///     }
+/// }
+/// assert_eq!(words, 2);
+/// ```
+pub struct EventReader<'input> {
+    stream: Stream<'input>,
+    state: State,
+    ctx: EventContext<'input>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Prolog,
+    Content,
+    Done,
+}
+
+impl<'input> EventReader<'input> {
+    /// Creates a reader of the input XML string.
+    pub fn new(text: &'input str) -> Self {
+        Self::with_options(text, ParsingOptions::default())
+    }
+
+    /// Creates a reader of the input XML string using the specified parsing
+    /// options.
+    pub fn with_options(text: &'input str, opt: ParsingOptions) -> Self {
+        EventReader {
+            stream: Stream::new(text),
+            state: State::Prolog,
+            ctx: EventContext {
+                text,
+                resolver: Resolver::new(text, opt),
+                events: VecDeque::new(),
+                elements: Vec::new(),
+                namespaces: Vec::new(),
+                tag: None,
+                attributes: Vec::new(),
+                text_start: 0,
+                entity_pos: None,
+                entity_floor: 0,
+                has_root: false,
+                last_pos: (0, TextPos::new(1, 1)),
+            },
+        }
+    }
+
+    /// Returns the errors which were recovered from so far.
+    ///
+    /// Always empty unless [`ParsingOptions::recover`] is set.
+    pub fn warnings(&self) -> &[Error] {
+        self.ctx.resolver.warnings()
+    }
+
+    // Tokenizes the next part of the document.
+    fn read(&mut self) -> Result<()> {
+        match self.state {
+            State::Prolog => {
+                self.state = State::Content;
+                tokenizer::parse_prolog(&mut self.stream, &mut self.ctx)
+            }
+            State::Content if self.stream.at_end() => {
+                self.state = State::Done;
+                self.ctx.finish()
+            }
+            State::Content => {
+                tokenizer::parse_content_item(&mut self.stream, &mut self.ctx)?;
+                Ok(())
+            }
+            State::Done => Ok(()),
+        }
+    }
+}
+
+impl<'input> Iterator for EventReader<'input> {
+    type Item = Result<Event<'input>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.ctx.events.is_empty() && self.state != State::Done {
+            if let Err(error) = self.read() {
+                self.state = State::Done;
+                self.ctx.events.clear();
+                return Some(Err(error));
+            }
+        }
+        self.ctx.events.pop_front().map(Ok)
+    }
+}
+
+// An element whose end hasn't been read yet.
+struct OpenElement<'input> {
+    name: Name<'input>,
+    // Number of namespaces declared before the element.
+    namespaces: usize,
+}
+
+// A start tag being read.
+struct StartTag<'input> {
+    prefix: &'input str,
+    local: &'input str,
+    start: usize,
+    // Number of namespaces declared before the tag.
+    namespaces: usize,
+}
+
+// An attribute of the start tag being read.
+struct TempAttribute<'input> {
+    prefix: &'input str,
+    local: &'input str,
+    value: StringStorage<'input>,
+    start: usize,
+}
+
+// Turns the tokens into events.
+struct EventContext<'input> {
+    text: &'input str,
+    resolver: Resolver<'input>,
+    events: VecDeque<Event<'input>>,
+    elements: Vec<OpenElement<'input>>,
+    // Namespaces declared by the open elements, innermost last.
+    namespaces: Vec<(Option<&'input str>, StringStorage<'input>)>,
+    tag: Option<StartTag<'input>>,
+    attributes: Vec<TempAttribute<'input>>,
+    // Where the text being resolved starts.
+    text_start: usize,
+    // Where the entity being expanded is referenced, events inside of it
+    // take this position.
+    entity_pos: Option<usize>,
+    // Number of open elements which the expanded entity can't close.
+    entity_floor: usize,
+    has_root: bool,
+    // The last position computed, to go on from there.
+    last_pos: (usize, TextPos),
+}
+
+impl<'input> EventContext<'input> {
+    fn push(&mut self, event: Event<'input>) {
+        self.events.push_back(event);
+    }
+
+    // Returns the position of the event at `offset`. Events mostly come in
+    // the order of the text, so the rows and columns are counted on from the
+    // previous event.
+    fn pos_at(&mut self, offset: usize) -> TextPos {
+        let offset = self.entity_pos.unwrap_or(offset);
+        let (last_offset, mut pos) = self.last_pos;
+        if offset < last_offset {
+            return Stream::new(self.text).gen_text_pos_from(offset);
+        }
+        for c in self.text[last_offset..offset].chars() {
+            if c == '\n' {
+                pos.row += 1;
+                pos.col = 1;
+            } else {
+                pos.col += 1;
+            }
+        }
+        self.last_pos = (offset, pos);
+        pos
+    }
+
+    fn err_pos_at(&self, offset: usize) -> TextPos {
+        Stream::new(self.text).gen_text_pos_from(self.entity_pos.unwrap_or(offset))
+    }
+
+    // Returns the namespace bound to the prefix of a name at `offset`.
+    // Attributes without a prefix have no namespace.
+    fn namespace(
+        &self,
+        prefix: &str,
+        offset: usize,
+        is_attribute: bool,
+    ) -> Result<Option<StringStorage<'input>>> {
+        if prefix == NS_XML_PREFIX {
+            return Ok(Some(StringStorage::Borrowed(NS_XML_URI)));
+        }
+        if prefix.is_empty() && is_attribute {
+            return Ok(None);
+        }
+
+        let name = (!prefix.is_empty()).then_some(prefix);
+        match self.namespaces.iter().rev().find(|(n, _)| *n == name) {
+            // `xmlns=""` undeclares the default namespace.
+            Some((_, uri)) if uri.is_empty() => Ok(None),
+            Some((_, uri)) => Ok(Some(uri.clone())),
+            None if prefix.is_empty() => Ok(None),
+            None => Err(Error::UnknownNamespace(
+                prefix.to_string(),
+                self.err_pos_at(offset),
+            )),
+        }
+    }
+
+    fn declare_namespace(
+        &mut self,
+        start: usize,
+        name: Option<&'input str>,
+        uri: StringStorage<'input>,
+    ) -> Result<()> {
+        // The xmlns namespace can't be declared, and the xml namespace only
+        // with its own prefix.
+        let is_xml_uri = uri.as_str() == NS_XML_URI;
+        if uri.as_str() == NS_XMLNS_URI {
+            return Err(Error::UnexpectedXmlnsUri(self.err_pos_at(start)));
+        }
+        if name == Some(NS_XML_PREFIX) {
+            if !is_xml_uri {
+                return Err(Error::InvalidXmlPrefixUri(self.err_pos_at(start)));
+            }
+            return Ok(());
+        }
+        if is_xml_uri {
+            return Err(Error::UnexpectedXmlUri(self.err_pos_at(start)));
+        }
+
+        let tag_namespaces = self.tag.as_ref().map_or(0, |tag| tag.namespaces);
+        if self.namespaces[tag_namespaces..]
+            .iter()
+            .any(|(n, _)| *n == name)
+        {
+            let name = name.unwrap_or_default().to_string();
+            return Err(Error::DuplicatedNamespace(name, self.err_pos_at(start)));
+        }
+        self.namespaces.push((name, uri));
+        Ok(())
+    }
+
+    // Emits the start of the element whose tag was read, and its end too if
+    // the tag is empty.
+    fn start_element(&mut self, end: ElementEnd, end_start: usize) -> Result<()> {
+        let Some(tag) = self.tag.take() else {
+            return Ok(());
+        };
+
+        let name = Name {
+            namespace: self.namespace(tag.prefix, tag.start + 1, false)?,
+            prefix: tag.prefix,
+            local: tag.local,
+        };
+        let mut attributes: Vec<EventAttribute> = Vec::with_capacity(self.attributes.len());
+        for attr in core::mem::take(&mut self.attributes) {
+            let name = Name {
+                namespace: self.namespace(attr.prefix, attr.start, true)?,
+                prefix: attr.prefix,
+                local: attr.local,
+            };
+            // Prefixes bound to the same namespace make the same name.
+            let duplicate = attributes
+                .iter()
+                .any(|a| a.name.local == name.local && a.name.namespace == name.namespace);
+            if duplicate {
+                let pos = self.err_pos_at(attr.start);
+                return Err(Error::DuplicatedAttribute(attr.local.to_string(), pos));
+            }
+            attributes.push(EventAttribute {
+                name,
+                value: attr.value,
+            });
+        }
+
+        let pos = self.pos_at(tag.start);
+        self.push(Event::StartElement {
+            name: name.clone(),
+            attributes,
+            pos,
+        });
+        self.has_root = true;
+
+        if let ElementEnd::Empty = end {
+            let pos = self.pos_at(end_start);
+            self.push(Event::EndElement { name, pos });
+            self.namespaces.truncate(tag.namespaces);
+        } else {
+            self.elements.push(OpenElement {
+                name,
+                namespaces: tag.namespaces,
+            });
+        }
+        Ok(())
+    }
+
+    // Ends the innermost open element.
+    fn end_element(&mut self, offset: usize) {
+        let element = self.elements.pop().unwrap();
+        self.namespaces.truncate(element.namespaces);
+        let pos = self.pos_at(offset);
+        self.push(Event::EndElement {
+            name: element.name,
+            pos,
+        });
+    }
+
+    fn close_tag(&mut self, prefix: &str, local: &str, start: usize) -> Result<()> {
+        // Number of open elements inside the closed one, which only a
+        // malformed document leaves open.
+        let depth = self.elements[self.entity_floor..]
+            .iter()
+            .rev()
+            .position(|e| e.name.prefix == prefix && e.name.local == local);
+
+        let pos = self.err_pos_at(start);
+        let qname = gen_qname_string(prefix, local);
+        let Some(depth) = depth else {
+            // No open element has the name, the tag is left out.
+            return self.resolver.warn(if self.entity_pos.is_some() {
+                Error::UnexpectedEntityCloseTag(pos)
+            } else if let Some(element) = self.elements.last() {
+                let expected = gen_qname_string(element.name.prefix, element.name.local);
+                Error::UnexpectedCloseTag(expected, qname, pos)
+            } else {
+                Error::UnknownToken(pos)
+            });
+        };
+        for _ in 0..depth {
+            let element = self.elements.last().unwrap();
+            let expected = gen_qname_string(element.name.prefix, element.name.local);
+            self.resolver
+                .warn(Error::UnexpectedCloseTag(expected, qname.clone(), pos))?;
+            self.end_element(start);
+        }
+        self.end_element(start);
+        Ok(())
+    }
+
+    // Checks the document when it has been read, closing what is still open.
+    fn finish(&mut self) -> Result<()> {
+        if !self.has_root {
+            return Err(Error::NoRootNode);
+        }
+        if !self.elements.is_empty() {
+            self.resolver.warn(Error::UnclosedRootNode)?;
+            while !self.elements.is_empty() {
+                self.end_element(self.text.len());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'input> tokenizer::XmlEvents<'input> for EventContext<'input> {
+    fn token(&mut self, token: Token<'input>) -> Result<()> {
+        match token {
+            Token::ElementStart(prefix, local, start) => {
+                if prefix == XMLNS {
+                    return Err(Error::InvalidElementNamePrefix(self.err_pos_at(start + 1)));
+                }
+                if self.elements.is_empty() && self.has_root {
+                    // Another root element.
+                    self.resolver
+                        .warn(Error::UnknownToken(self.err_pos_at(start)))?;
+                }
+                self.tag = Some(StartTag {
+                    prefix,
+                    local,
+                    start,
+                    namespaces: self.namespaces.len(),
+                });
+            }
+            Token::Attribute(range, _, _, prefix, local, value) => {
+                let value = normalize_attribute(value, &mut self.resolver)?;
+                if prefix == XMLNS {
+                    self.declare_namespace(range.start, Some(local), value)?;
+                } else if prefix.is_empty() && local == XMLNS {
+                    self.declare_namespace(range.start, None, value)?;
+                } else {
+                    self.attributes.push(TempAttribute {
+                        prefix,
+                        local,
+                        value,
+                        start: range.start,
+                    });
+                }
+            }
+            Token::ElementEnd(ElementEnd::Close(prefix, local), range) => {
+                self.close_tag(prefix, local, range.start)?;
+            }
+            Token::ElementEnd(end, range) => {
+                self.start_element(end, range.start)?;
+            }
+            Token::Text(text, range) => {
+                if self.elements.is_empty() {
+                    // Only whitespace may go around the root element.
+                    if text.trim_start_matches([' ', '\t', '\n', '\r']).is_empty() {
+                        return Ok(());
+                    }
+                    self.resolver
+                        .warn(Error::UnknownToken(self.err_pos_at(range.start)))?;
+                }
+                self.text_start = range.start;
+                process_text(text, range, self)?;
+            }
+            Token::Cdata(text, range) => {
+                let pos = self.pos_at(range.start);
+                self.push(Event::Cdata { text, pos });
+            }
+            Token::Comment(text, range) => {
+                let pos = self.pos_at(range.start);
+                self.push(Event::Comment { text, pos });
+            }
+            Token::EntityDeclaration(name, value) => {
+                self.resolver.declare(name, value);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn recover(&mut self, error: Error) -> Result<()> {
+        self.resolver.warn(error)
+    }
+}
+
+impl<'input> TextSink<'input> for EventContext<'input> {
+    fn resolver(&mut self) -> &mut Resolver<'input> {
+        &mut self.resolver
+    }
+
+    fn append_text(&mut self, text: StringStorage<'input>) -> Result<()> {
+        let pos = self.pos_at(self.text_start);
+        self.push(Event::Text { text, pos });
+        Ok(())
+    }
+
+    fn parse_entity_markup(&mut self, value: StrSpan<'input>, pos: usize) -> Result<()> {
+        // Entities can't close the elements open where they are referenced.
+        let outer = (self.entity_pos, self.entity_floor, self.text_start);
+        self.entity_pos = Some(outer.0.unwrap_or(pos));
+        self.entity_floor = self.elements.len();
+
+        let mut stream = Stream::from_substr(self.text, value.range());
+        while !stream.at_end() {
+            tokenizer::parse_content_item(&mut stream, self)?;
+        }
+
+        (self.entity_pos, self.entity_floor, self.text_start) = outer;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Document, Node};
+    use super::*;
+
+    const RECOVER: ParsingOptions = ParsingOptions {
+        html_entities: true,
+        recover: true,
+    };
+
+    fn expanded(namespace: Option<&str>, local: &str) -> String {
+        // The tree keeps the empty URI of `xmlns=""`, where the events have
+        // no namespace.
+        match namespace.filter(|namespace| !namespace.is_empty()) {
+            Some(namespace) => format!("{{{}}}{}", namespace, local),
+            None => local.to_string(),
+        }
+    }
+
+    // Adds text to the last line if it is text too, as the tree joins what
+    // the events may split.
+    fn push_text(lines: &mut Vec<String>, text: &str) {
+        match lines.last_mut() {
+            Some(last) if last.starts_with("text ") => last.push_str(text),
+            _ => lines.push(format!("text {}", text)),
+        }
+    }
+
+    fn write_node(node: Node, lines: &mut Vec<String>) {
+        if node.is_text() {
+            return push_text(lines, node.text().unwrap());
+        }
+        let name = expanded(node.tag_name().namespace(), node.tag_name().name());
+        lines.push(format!("start {}", name));
+        for attr in node.attributes() {
+            let attr_name = attr.data.name.as_expanded_name(attr.doc);
+            let attr_name = expanded(attr_name.namespace(), attr_name.name());
+            lines.push(format!("attribute {}={}", attr_name, attr.value()));
+        }
+        for child in node.children() {
+            write_node(child, lines);
+        }
+        lines.push(format!("end {}", name));
+    }
+
+    // The document as lines, by way of the tree and by way of the events.
+    fn parse_both(xml: &str, opt: ParsingOptions) -> (Vec<String>, Vec<String>) {
+        let doc = Document::parse_with_options(xml, opt).unwrap();
+        let mut tree = Vec::new();
+        for node in doc.root().children() {
+            write_node(node, &mut tree);
+        }
+
+        let mut events = Vec::new();
+        let mut reader = EventReader::with_options(xml, opt);
+        for event in reader.by_ref() {
+            match event.unwrap() {
+                Event::StartElement {
+                    name, attributes, ..
+                } => {
+                    let name = expanded(name.namespace.as_deref(), name.local);
+                    events.push(format!("start {}", name));
+                    for attr in attributes {
+                        let attr_name = expanded(attr.name.namespace.as_deref(), attr.name.local);
+                        events.push(format!("attribute {}={}", attr_name, attr.value.as_str()));
+                    }
+                }
+                Event::EndElement { name, .. } => {
+                    let name = expanded(name.namespace.as_deref(), name.local);
+                    events.push(format!("end {}", name));
+                }
+                Event::Text { text, .. } => push_text(&mut events, text.as_str()),
+                // The tree keeps neither CDATA nor comments.
+                Event::Cdata { .. } | Event::Comment { .. } => {}
+            }
+        }
+        assert_eq!(reader.warnings().len(), doc.warnings().len());
+        (tree, events)
+    }
+
+    fn assert_same(xml: &str, opt: ParsingOptions) {
+        let (tree, events) = parse_both(xml, opt);
+        assert_eq!(events, tree, "{}", xml);
+    }
+
+    #[test]
+    fn events_match_tree() {
+        let documents = [
+            "<r/>",
+            "<?xml version='1.0'?>\n<!-- before -->\n<r>text</r>\n<!-- after -->\n",
+            "<r a='1' b=\"2\">\n  <e>one</e>\n  <e/>\n</r>",
+            "<r xmlns='u' xmlns:p='v'><p:e p:a='1' a='2'><e xmlns=''/></p:e></r>",
+            "<r xml:lang='en'><e xmlns:p='u'/><p:e xmlns:p='v'/></r>",
+            "<r>a&lt;b&#x20;&#65;<![CDATA[<c>]]>d<!-- e -->f</r>",
+            "<r a=' x&#10;y\tz '/>",
+            "<!DOCTYPE r [<!ENTITY e '<b>bold</b> &amp; text'>]><r>x&e;y&e;</r>",
+            "<!DOCTYPE r [<!ENTITY e 'value'>]><r a='&e;'/>",
+        ];
+        for xml in documents {
+            assert_same(xml, ParsingOptions::default());
+            assert_same(xml, RECOVER);
+        }
+    }
+
+    #[test]
+    fn events_match_tree_when_recovering() {
+        let documents = [
+            "<r>&nbsp;&mdash;</r>",
+            "<r><p><b>bold</p>after</r>",
+            "<r><p>text</i></p></r>",
+            "<r><p>unclosed",
+            "<r>a & b</r>",
+            "<r>one</r><r>two</r>",
+            "<!DOCTYPE r [<!ENTITY e '<b>open'>]><r>&e;</b></r>",
+            "<!DOCTYPE r [<!ENTITY e '<b>x</b></p>'>]><r><p>&e;y</p></r>",
+            "<r a='x\u{1}y'>t\u{1}u</r>",
+        ];
+        for xml in documents {
+            assert_same(xml, RECOVER);
+        }
+    }
+
+    #[test]
+    fn errors_match_tree() {
+        let documents = [
+            "",
+            "<r>",
+            "<r></e>",
+            "<p:r/>",
+            "<r a='1' a='2'/>",
+            "<r xmlns:p='u' xmlns:q='u' p:x='1' q:x='2'/>",
+            "<r xmlns:p='u' xmlns:p='v'/>",
+            "<r xmlns:xml='u'/>",
+            "<r>&nbsp;</r>",
+            "<r>&#0;</r>",
+            "<!DOCTYPE r [<!ENTITY e '<b>x</b></p>'>]><r><p>&e;y</p></r>",
+        ];
+        for xml in documents {
+            let tree = Document::parse(xml).map(|_| ());
+            let events: Result<Vec<Event>> = EventReader::new(xml).collect();
+            assert_eq!(
+                events.map(|_| ()).map_err(|e| e.to_string()),
+                tree.map_err(|e| e.to_string()),
+                "{}",
+                xml
+            );
+        }
+    }
+
+    #[test]
+    fn same_local_name_in_other_namespaces() {
+        let xml = "<r xmlns:p='u' xmlns:q='v' x='0' p:x='1' q:x='2'/>";
+        assert_same(xml, ParsingOptions::default());
+    }
+
+    #[test]
+    fn positions() {
+        let xml = "<r>\n  <e a='1'/>text<![CDATA[c]]>\n</r>";
+        let positions: Vec<(u32, u32)> = EventReader::new(xml)
+            .map(|event| event.unwrap().pos())
+            .map(|pos| (pos.row, pos.col))
+            .collect();
+        assert_eq!(
+            positions,
+            [
+                (1, 1),
+                (1, 4),
+                (2, 3),
+                (2, 11),
+                (2, 13),
+                (2, 17),
+                (2, 30),
+                (3, 1)
+            ]
+        );
+    }
+}
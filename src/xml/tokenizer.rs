@@ -6,7 +6,7 @@ use super::{Error, TextPos};
 type Result<T> = core::result::Result<T, Error>;
 
 /// Extension methods for XML-subset only operations.
-trait XmlCharExt {
+pub(crate) trait XmlCharExt {
     /// Checks if the value is within the
     /// [NameStartChar](https://www.w3.org/TR/xml/#NT-NameStartChar) range.
     fn is_xml_name_start(&self) -> bool;
@@ -122,7 +122,7 @@ impl<'input> From<&'input str> for StrSpan<'input> {
 
 impl<'input> StrSpan<'input> {
     #[inline]
-    pub fn from_substr(text: &str, start: usize, end: usize) -> StrSpan {
+    pub fn from_substr(text: &str, start: usize, end: usize) -> StrSpan<'_> {
         debug_assert!(start <= end);
         StrSpan {
             text: &text[start..end],
@@ -166,6 +166,15 @@ pub enum Token<'input> {
     // Basically everything between `>` and `<`.
     // Except `]]>`, which is not allowed and will lead to an error.
     Text(&'input str, Range<usize>),
+
+    // <![CDATA[text]]>
+    Cdata(&'input str, Range<usize>),
+
+    // <!--text-->
+    Comment(&'input str, Range<usize>),
+
+    // <!ENTITY name "value">
+    EntityDeclaration(&'input str, StrSpan<'input>),
 }
 
 /// `ElementEnd` token.
@@ -181,12 +190,57 @@ pub enum ElementEnd<'input> {
 
 pub trait XmlEvents<'input> {
     fn token(&mut self, token: Token<'input>) -> Result<()>;
+
+    /// Called with an error the tokenizer can skip over. Parsing fails with
+    /// the returned error, if any.
+    fn recover(&mut self, error: Error) -> Result<()> {
+        Err(error)
+    }
+}
+
+/// What [`parse_content_item`] has read.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Item {
+    /// The start tag of an element with content.
+    OpenTag,
+    /// A close tag.
+    CloseTag,
+    /// Anything else, including an empty element tag.
+    Other,
 }
 
 // document ::= prolog element Misc*
 pub fn parse<'input>(text: &'input str, events: &mut dyn XmlEvents<'input>) -> Result<()> {
     let s = &mut Stream::new(text);
 
+    parse_prolog(s, events)?;
+
+    s.skip_spaces();
+    if s.curr_byte().ok() == Some(b'<') {
+        parse_element(s, events)?;
+    }
+
+    parse_misc(s, events)?;
+
+    // A recovering parser may end elements early, and keeps parsing what follows.
+    while !s.at_end() {
+        if s.starts_with(b"</") {
+            parse_close_element(s, events)?;
+        } else {
+            events.recover(Error::UnknownToken(s.gen_text_pos()))?;
+            parse_content(s, events)?;
+        }
+        parse_misc(s, events)?;
+    }
+
+    Ok(())
+}
+
+// prolog ::= XMLDecl? Misc* (doctypedecl Misc*)?
+pub fn parse_prolog<'input>(
+    s: &mut Stream<'input>,
+    events: &mut dyn XmlEvents<'input>,
+) -> Result<()> {
     // Skip UTF-8 BOM.
     if s.starts_with(&[0xEF, 0xBB, 0xBF]) {
         s.advance(3);
@@ -196,34 +250,23 @@ pub fn parse<'input>(text: &'input str, events: &mut dyn XmlEvents<'input>) -> R
         parse_declaration(s)?;
     }
 
-    parse_misc(s)?;
+    parse_misc(s, events)?;
 
     s.skip_spaces();
     if s.starts_with(b"<!DOCTYPE") {
-        skip_doctype(s)?;
-        parse_misc(s)?;
-    }
-
-    s.skip_spaces();
-    if s.curr_byte().ok() == Some(b'<') {
-        parse_element(s, events)?;
-    }
-
-    parse_misc(s)?;
-
-    if !s.at_end() {
-        return Err(Error::UnknownToken(s.gen_text_pos()));
+        parse_doctype(s, events)?;
+        parse_misc(s, events)?;
     }
 
     Ok(())
 }
 
 // Misc ::= Comment | PI | S
-fn parse_misc<'input>(s: &mut Stream<'input>) -> Result<()> {
+fn parse_misc<'input>(s: &mut Stream<'input>, events: &mut dyn XmlEvents<'input>) -> Result<()> {
     while !s.at_end() {
         s.skip_spaces();
         if s.starts_with(b"<!--") {
-            skip_comment(s)?;
+            events.token(parse_comment(s)?)?;
         } else if s.starts_with(b"<?") {
             skip_pi(s)?;
         } else {
@@ -279,11 +322,12 @@ fn parse_declaration(s: &mut Stream) -> Result<()> {
 }
 
 // '<!--' ((Char - '-') | ('-' (Char - '-')))* '-->'
-fn skip_comment<'input>(s: &mut Stream<'input>) -> Result<()> {
+fn parse_comment<'input>(s: &mut Stream<'input>) -> Result<Token<'input>> {
+    let start = s.pos();
     s.advance(4);
-    s.skip_chars(|s, c| !(c == '-' && s.starts_with(b"-->")))?;
+    let text = s.consume_chars(|s, c| !(c == '-' && s.starts_with(b"-->")))?;
     s.skip_string(b"-->")?;
-    Ok(())
+    Ok(Token::Comment(text, s.range_from(start)))
 }
 
 // PI       ::= '<?' PITarget (S (Char* - (Char* '?>' Char*)))? '?>'
@@ -298,30 +342,65 @@ fn skip_pi<'input>(s: &mut Stream<'input>) -> Result<()> {
 // EntityDecl  ::= GEDecl | PEDecl
 // GEDecl      ::= '<!ENTITY' S Name S EntityDef S? '>'
 // PEDecl      ::= '<!ENTITY' S '%' S Name S PEDef S? '>'
-fn skip_entity<'input>(s: &mut Stream<'input>) -> Result<()> {
-    s.advance(8);
-    s.skip_chars(|_, c| !(c == '\'' || c == '"' ))?;
+// EntityDef   ::= EntityValue | (ExternalID NDataDecl?)
+//
+// Only general entities with a value are reported, parameter and external
+// entities are skipped.
+fn parse_entity_decl<'input>(
+    s: &mut Stream<'input>,
+    events: &mut dyn XmlEvents<'input>,
+) -> Result<()> {
+    s.advance(8); // <!ENTITY
+    s.consume_spaces()?;
+    let is_parameter = s.try_consume_byte(b'%');
+    if is_parameter {
+        s.consume_spaces()?;
+    }
+    let name = s.consume_name()?;
+    s.consume_spaces()?;
+
+    if s.starts_with(b"SYSTEM") || s.starts_with(b"PUBLIC") {
+        // Skip the keyword and the quoted literals following it.
+        while s.curr_byte()? != b'>' {
+            match s.curr_byte_unchecked() {
+                quote @ (b'"' | b'\'') => {
+                    s.advance(1);
+                    s.skip_bytes(|c| c != quote);
+                    s.consume_byte(quote)?;
+                }
+                _ => s.advance(1),
+            }
+        }
+        s.advance(1);
+        return Ok(());
+    }
 
-    match s.curr_byte()? {
-        b'\'' => s.skip_chars(|s, c| !(c == '\'' && s.starts_with(b"'>")))?,
-        b'"' => s.skip_chars(|s, c| !(c == '"' && s.starts_with(b"\">") ))?,
-        _ => (),
+    let quote = s.consume_quote()?;
+    let value_start = s.pos();
+    s.skip_bytes(|c| c != quote);
+    let value = s.slice_back_span(value_start);
+    s.consume_byte(quote)?;
+    s.skip_spaces();
+    s.consume_byte(b'>')?;
+
+    if !is_parameter {
+        events.token(Token::EntityDeclaration(name, value))?;
     }
-    s.advance(2);
     Ok(())
 }
 
 // CDSect  ::= CDStart CData CDEnd
 // CDStart ::= '<![CDATA['
 // CDEnd   ::= ']]>'
-fn skip_cdata<'input>(s: &mut Stream<'input>) -> Result<()> {
+fn parse_cdata<'input>(s: &mut Stream<'input>) -> Result<Token<'input>> {
+    let start = s.pos();
     s.advance(9); // <![CDATA[
-    s.skip_chars(|s, c| !(c == ']' && s.starts_with(b"]]>")))?;
+    let text = s.consume_chars(|s, c| !(c == ']' && s.starts_with(b"]]>")))?;
     s.skip_string(b"]]>")?;
-    Ok(())
+    Ok(Token::Cdata(text, s.range_from(start)))
 }
 
-fn skip_doctype<'input>(s: &mut Stream<'input>) -> Result<()> {
+fn parse_doctype<'input>(s: &mut Stream<'input>, events: &mut dyn XmlEvents<'input>) -> Result<()> {
     s.advance(1);
     s.consume_chars(|_, c| !(c == '<' || c == '>'))?;
 
@@ -333,11 +412,12 @@ fn skip_doctype<'input>(s: &mut Stream<'input>) -> Result<()> {
     while !s.at_end() {
         s.skip_spaces();
         if s.starts_with(b"<!ENTITY") {
-            skip_entity(s)?;
+            parse_entity_decl(s, events)?;
         } else if s.starts_with(b"<?") {
             skip_pi(s)?;
         } else if s.starts_with(b"<!--") {
-            skip_comment(s)?;
+            // Comments of the DTD aren't reported.
+            parse_comment(s)?;
         } else if s.starts_with(b"<!ELEMENT")
             || s.starts_with(b"<!ATTLIST")
             || s.starts_with(b"<!NOTATION")
@@ -369,8 +449,21 @@ fn skip_doctype<'input>(s: &mut Stream<'input>) -> Result<()> {
 }
 
 // element ::= EmptyElemTag | STag content ETag
-// '<' Name (S Attribute)* S? '>'
 fn parse_element<'input>(s: &mut Stream<'input>, events: &mut dyn XmlEvents<'input>) -> Result<()> {
+    if parse_start_tag(s, events)? {
+        parse_content(s, events)?;
+    }
+
+    Ok(())
+}
+
+// '<' Name (S Attribute)* S? '>'
+//
+// Returns whether the element has content, i.e. isn't an empty element tag.
+fn parse_start_tag<'input>(
+    s: &mut Stream<'input>,
+    events: &mut dyn XmlEvents<'input>,
+) -> Result<bool> {
     let start = s.pos();
     s.advance(1); // <
     let (prefix, local) = s.consume_qname()?;
@@ -415,7 +508,7 @@ fn parse_element<'input>(s: &mut Stream<'input>, events: &mut dyn XmlEvents<'inp
                 let quote_c = quote as char;
                 // The attribute value must not contain the < character.
                 let value_start = s.pos();
-                s.skip_chars(|_, c| c != quote_c && c != '<')?;
+                skip_attribute_value(s, quote_c, events)?;
                 let value = s.slice_back_span(value_start);
                 s.consume_byte(quote)?;
                 let end = s.pos();
@@ -431,11 +524,25 @@ fn parse_element<'input>(s: &mut Stream<'input>, events: &mut dyn XmlEvents<'inp
         }
     }
 
-    if open {
-        parse_content(s, events)?;
-    }
+    Ok(open)
+}
 
-    Ok(())
+// Skips to the quote ending an attribute value. When recovering, invalid
+// characters are left in the value, for the parser to drop.
+fn skip_attribute_value<'input>(
+    s: &mut Stream<'input>,
+    quote_c: char,
+    events: &mut dyn XmlEvents<'input>,
+) -> Result<()> {
+    loop {
+        match s.skip_chars(|_, c| c != quote_c && c != '<') {
+            Err(Error::NonXmlChar(c, pos)) => {
+                events.recover(Error::NonXmlChar(c, pos))?;
+                s.advance(c.len_utf8());
+            }
+            result => return result,
+        }
+    }
 }
 
 // Attribute ::= Name Eq AttValue
@@ -460,31 +567,51 @@ pub fn parse_content<'input>(
     events: &mut dyn XmlEvents<'input>,
 ) -> Result<()> {
     while !s.at_end() {
-        match s.curr_byte() {
-            Ok(b'<') => match s.next_byte() {
-                Ok(b'!') => {
-                    if s.starts_with(b"<!--") {
-                        skip_comment(s)?;
-                    } else if s.starts_with(b"<![CDATA[") {
-                        skip_cdata(s)?;
-                    } else {
-                        return Err(Error::UnknownToken(s.gen_text_pos()));
-                    }
+        match parse_content_item(s, events)? {
+            Item::OpenTag => parse_content(s, events)?,
+            Item::CloseTag => break,
+            Item::Other => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses a single item of content: a tag, text, CDATA, a comment or a
+/// processing instruction. Unlike [`parse_content`] it doesn't descend into
+/// elements, so the caller can read a document piece by piece.
+pub fn parse_content_item<'input>(
+    s: &mut Stream<'input>,
+    events: &mut dyn XmlEvents<'input>,
+) -> Result<Item> {
+    match s.curr_byte() {
+        Ok(b'<') => match s.next_byte() {
+            Ok(b'!') => {
+                if s.starts_with(b"<!--") {
+                    events.token(parse_comment(s)?)?;
+                } else if s.starts_with(b"<![CDATA[") {
+                    events.token(parse_cdata(s)?)?;
+                } else {
+                    return Err(Error::UnknownToken(s.gen_text_pos()));
                 }
-                Ok(b'?') => skip_pi(s)?,
-                Ok(b'/') => {
-                    parse_close_element(s, events)?;
-                    break;
+            }
+            Ok(b'?') => skip_pi(s)?,
+            Ok(b'/') => {
+                parse_close_element(s, events)?;
+                return Ok(Item::CloseTag);
+            }
+            Ok(_) => {
+                if parse_start_tag(s, events)? {
+                    return Ok(Item::OpenTag);
                 }
-                Ok(_) => parse_element(s, events)?,
-                Err(_) => return Err(Error::UnknownToken(s.gen_text_pos())),
-            },
-            Ok(_) => parse_text(s, events)?,
+            }
             Err(_) => return Err(Error::UnknownToken(s.gen_text_pos())),
-        }
+        },
+        Ok(_) => parse_text(s, events)?,
+        Err(_) => return Err(Error::UnknownToken(s.gen_text_pos())),
     }
 
-    Ok(())
+    Ok(Item::Other)
 }
 
 // '</' Name S? '>'
@@ -509,7 +636,19 @@ fn parse_close_element<'input>(
 
 fn parse_text<'input>(s: &mut Stream<'input>, events: &mut dyn XmlEvents<'input>) -> Result<()> {
     let start = s.pos();
-    let text = s.consume_chars(|_, c| c != '<')?;
+    let text = match s.consume_chars(|_, c| c != '<') {
+        Err(Error::NonXmlChar(c, pos)) => {
+            // Skip the character, the text after it follows in another token.
+            events.recover(Error::NonXmlChar(c, pos))?;
+            let text = s.slice_back(start);
+            if !text.is_empty() {
+                events.token(Token::Text(text, s.range_from(start)))?;
+            }
+            s.advance(c.len_utf8());
+            return Ok(());
+        }
+        text => text?,
+    };
 
     // According to the spec, `]]>` must not appear inside a Text node.
     // https://www.w3.org/TR/xml/#syntax
@@ -526,11 +665,16 @@ fn parse_text<'input>(s: &mut Stream<'input>, events: &mut dyn XmlEvents<'input>
 
 /// Representation of the [Reference](https://www.w3.org/TR/xml/#NT-Reference) value.
 #[derive(Clone, Copy)]
-pub enum Reference {
+pub enum Reference<'input> {
     /// A character reference.
     ///
     /// <https://www.w3.org/TR/xml/#NT-CharRef>
     Char(char),
+
+    /// An entity reference other than the predefined ones, with its name.
+    ///
+    /// <https://www.w3.org/TR/xml/#NT-EntityRef>
+    Entity(&'input str),
 }
 
 #[derive(Clone)]
@@ -730,7 +874,7 @@ impl<'input> Stream<'input> {
     }
 
     /// Consumes according to: <https://www.w3.org/TR/xml/#NT-Reference>
-    pub fn try_consume_reference(&mut self) -> Option<Reference> {
+    pub fn try_consume_reference(&mut self) -> Option<Reference<'input>> {
         let start = self.pos();
 
         // Consume reference on a substream.
@@ -744,7 +888,7 @@ impl<'input> Stream<'input> {
     }
 
     #[inline(never)]
-    fn consume_reference(&mut self) -> Option<Reference> {
+    fn consume_reference(&mut self) -> Option<Reference<'input>> {
         if !self.try_consume_byte(b'&') {
             return None;
         }
@@ -752,7 +896,7 @@ impl<'input> Stream<'input> {
         let reference = if self.try_consume_byte(b'#') {
             let (value, radix) = if self.try_consume_byte(b'x') {
                 let value =
-                    self.consume_bytes(|c| matches!(c, b'0'..=b'9' | b'A'..=b'F' | b'a'..=b'f'));
+                    self.consume_bytes(|c: u8| c.is_ascii_hexdigit());
                 (value, 16)
             } else {
                 let value = self.consume_bytes(|c| c.is_ascii_digit());
@@ -775,7 +919,7 @@ impl<'input> Stream<'input> {
                 "apos" => Reference::Char('\''),
                 "lt" => Reference::Char('<'),
                 "gt" => Reference::Char('>'),
-                _ => Reference::Char(' '),
+                _ => Reference::Entity(name),
             }
         };
 